@@ -2,7 +2,7 @@ extern crate cursebox;
 extern crate default_allocator;
 extern crate loca;
 
-use cursebox::{Attr, Cell, Event, Key};
+use cursebox::{Attr, Cell, Event, Key, Mod};
 
 const WIDTH : usize = 80;
 const HEIGHT: usize = 25;
@@ -25,18 +25,19 @@ fn main() {
         ui.set_cursor(pt.0, pt.1);
         ui.present();
         match ui.fetch_event(None) {
+            Ok(Some(Event::Key(Mod::Ctrl, Key::Char('c')))) => return,
+            Ok(Some(Event::Hangup)) => return,
             Ok(Some(Event::Key(mod_, key))) if mod_.is_empty() => match key {
                 Key::Left  => if pt.0 > 0          { pt.0 -= 1 },
                 Key::Right => if pt.0 < WIDTH  - 1 { pt.0 += 1 },
                 Key::Up    => if pt.1 > 0          { pt.1 -= 1 },
                 Key::Down  => if pt.1 < HEIGHT - 1 { pt.1 += 1 },
-                Key::Char('\x03') => return,
-                Key::Char('\n') | Key::Char('\r') => {
+                Key::Enter => {
                     pt.0 = 0;
                     pt.1 += 1;
                     if pt.1 >= HEIGHT { pt.1 -= HEIGHT }
                 },
-                Key::Char('\x08') => {
+                Key::Backspace => {
                     if pt.0 > 0 { pt.0 -= 1 }
                     cells[pt.1][pt.0].ch = ' ' as _;
                 },
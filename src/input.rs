@@ -1,56 +1,411 @@
 use core::num::NonZeroUsize;
+use loca::Alloc;
+use nul::NulStr;
 
+use query::TerminalInfo;
 use ringbuffer::Ringbuffer;
+use utf8::Decoded;
 
-pub(crate) const TB_KEYS_NUM: usize = 22;
+pub(crate) const TB_KEYS_NUM: usize = 84;
 
-static event_keys: [Key; TB_KEYS_NUM] = { use self::Key::*; [
-    F(1), F(2), F(3), F(4), F(5), F(6), F(7), F(8), F(9), F(10), F(11), F(12),
-    Insert, Delete, Home, End, PgUp, PgDn, Up, Down, Left, Right,
+static event_keys: [(Mod, Key); TB_KEYS_NUM] = { use self::Key::*; const N: Mod = Mod::empty(); [
+    (N, F(1)), (N, F(2)), (N, F(3)), (N, F(4)), (N, F(5)), (N, F(6)),
+    (N, F(7)), (N, F(8)), (N, F(9)), (N, F(10)), (N, F(11)), (N, F(12)),
+    (N, Insert), (N, Delete), (N, Home), (N, End), (N, PgUp), (N, PgDn),
+    (N, Up), (N, Down), (N, Left), (N, Right),
+    (N, F(13)), (N, F(14)), (N, F(15)), (N, F(16)), (N, F(17)), (N, F(18)), (N, F(19)), (N, F(20)),
+    (N, F(21)), (N, F(22)), (N, F(23)), (N, F(24)), (N, F(25)), (N, F(26)), (N, F(27)), (N, F(28)),
+    (N, F(29)), (N, F(30)), (N, F(31)), (N, F(32)), (N, F(33)), (N, F(34)), (N, F(35)), (N, F(36)),
+    (N, F(37)), (N, F(38)), (N, F(39)), (N, F(40)), (N, F(41)), (N, F(42)), (N, F(43)), (N, F(44)),
+    (N, F(45)), (N, F(46)), (N, F(47)), (N, F(48)), (N, F(49)), (N, F(50)), (N, F(51)), (N, F(52)),
+    (N, F(53)), (N, F(54)), (N, F(55)), (N, F(56)), (N, F(57)), (N, F(58)), (N, F(59)), (N, F(60)),
+    (N, F(61)), (N, F(62)), (N, F(63)),
+    (N, BackTab), (N, KpA1), (N, KpA3), (N, KpB2), (N, KpC1), (N, KpC3), (N, KpEnter), (N, Begin),
+    (Mod::Shift, Home), (Mod::Shift, End), (N, Backspace),
 ] };
 
-fn parse_escape_seq(buf: &[u8], keys: [&::nul::Nul<u8>; TB_KEYS_NUM]) -> Option<(Mod, Key, NonZeroUsize)> {
-    (0..TB_KEYS_NUM).find_map(|i| {
-        let key = &keys[i][..];
-        if let (Some(n), true) = (NonZeroUsize::new(key.len()), buf.starts_with(key)) {
-            Some((Mod::empty(), event_keys[i], n))
-        } else { None }
+/// Keys to recognize: those of terminfo, and those registered at run time
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KeyTable {
+    pub(crate) terminfo: [&'static NulStr; TB_KEYS_NUM],
+    custom: [CustomKey; CUSTOM_KEYS_MAX],
+    n_custom: usize,
+}
+
+pub(crate) const CUSTOM_KEYS_MAX: usize = 32;
+pub(crate) const CUSTOM_SEQ_LEN_MAX: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct CustomKey {
+    seq: [u8; CUSTOM_SEQ_LEN_MAX],
+    len: usize,
+    mod_: Mod,
+    key: Key,
+}
+
+impl KeyTable {
+    pub(crate) const fn new(terminfo: [&'static NulStr; TB_KEYS_NUM]) -> Self {
+        const empty: CustomKey = CustomKey { seq: [0; CUSTOM_SEQ_LEN_MAX], len: 0, mod_: Mod::empty(), key: Key::Esc };
+        Self { terminfo, custom: [empty; CUSTOM_KEYS_MAX], n_custom: 0 }
+    }
+
+    /// Recognize `seq` as `(mod_, key)`, replacing any former registration of `seq`.
+    /// Returns `false` if `seq` is empty or too long, or the table is full.
+    pub(crate) fn register(&mut self, seq: &[u8], mod_: Mod, key: Key) -> bool {
+        if 0 == seq.len() || seq.len() > CUSTOM_SEQ_LEN_MAX { return false }
+        let k = match self.custom[0..self.n_custom].iter().position(|c| *c.seq() == *seq) {
+            Some(k) => k,
+            None if self.n_custom < CUSTOM_KEYS_MAX => { self.n_custom += 1; self.n_custom - 1 },
+            None => return false,
+        };
+        let c = &mut self.custom[k];
+        c.seq[0..seq.len()].copy_from_slice(seq);
+        c.len = seq.len();
+        c.mod_ = mod_;
+        c.key = key;
+        true
+    }
+
+    /// Iterate over the keys, those registered at run time first.
+    fn iter<'a>(&'a self) -> impl 'a + Iterator<Item = (&'a [u8], (Mod, Key))> {
+        self.custom[0..self.n_custom].iter().map(|c| (c.seq(), (c.mod_, c.key)))
+            .chain(self.terminfo.iter().map(|s| &s.as_bytes()[..]).zip(event_keys.iter().cloned()))
+    }
+}
+
+impl CustomKey {
+    #[inline]
+    fn seq(&self) -> &[u8] { &self.seq[0..self.len] }
+}
+
+/// Find the longest key sequence which `buf` begins with; on a tie, the first.
+fn parse_escape_seq(buf: &[u8], keys: &KeyTable) -> Option<(Mod, Key, NonZeroUsize)> {
+    keys.iter().fold(None, |best, (seq, (mod_, key))| match NonZeroUsize::new(seq.len()) {
+        Some(n) if buf.starts_with(seq) && best.map_or(true, |(_, _, m)| n > m) => Some((mod_, key, n)),
+        _ => best,
     })
 }
 
-const BUFFER_SIZE_MAX: usize = 16;
+// long enough for replies to queries, e.g. DA1 with many features
+const BUFFER_SIZE_MAX: usize = 128;
 
-pub(crate) fn extract_event(inbuf: &mut Ringbuffer, mode: Mode, keys: [&::nul::Nul<u8>; TB_KEYS_NUM]) -> Option<(Mod, Key)> {
-    let mut buf: [u8; BUFFER_SIZE_MAX] = unsafe { ::core::mem::MaybeUninit::uninit().assume_init() };
-    let nbytes = ::core::cmp::min(inbuf.data_size(), buf.len());
-    if 0 == nbytes { return None }
+const one: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(1) };
 
-    inbuf.read(&mut buf[0..nbytes]);
-    if 0x1B == buf[0] {
-        if let Some((mod_, key, n)) = parse_escape_seq(&buf, keys) {
+fn parse_ctrl(b: u8) -> Option<(Mod, Key)> {
+    Some(match b {
+        b'\t' => (Mod::empty(), Key::Tab),
+        b'\r' => (Mod::empty(), Key::Enter),
+        0x1B => (Mod::empty(), Key::Esc),
+        0x08 | 0x7F => (Mod::empty(), Key::Backspace),
+        0x00 => (Mod::Ctrl, Key::Char(' ')),
+        0x01..=0x1A => (Mod::Ctrl, Key::Char((b'a' - 1 + b) as _)),
+        0x1C..=0x1F => (Mod::Ctrl, Key::Char((b | 0x40) as _)),
+        _ => return None,
+    })
+}
+
+/// State of input decoding
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    pub(crate) mode: Mode,
+    pub(crate) flags: Flags,
+    pub(crate) keys: KeyTable,
+    /// Number of cursor position reports requested and not yet received; while nonzero, input
+    /// which looks like one is taken as one, not as a key
+    pub(crate) cpr_pending: usize,
+    /// Whether a reply to DA1 is awaited, which is sent last in a probe
+    pub(crate) da_pending: bool,
+    pub(crate) info: TerminalInfo,
+    /// Capabilities told by XTGETTCAP
+    pub(crate) tcap: ::terminfo::Merge,
+    /// Whether the last `extract_event` took note of any reply
+    pub(crate) replied: bool,
+    /// Whether the rest of a control string too long to hold is being dropped, and if so, whether
+    /// the last byte dropped was an escape, which may begin its ST
+    long_str: Option<bool>,
+}
+
+/// What `Decoder::decode` found
+enum Token<'a> {
+    Event(Event),
+    /// Beginning of a control string too long to hold, the rest of which is to be dropped
+    LongStr(RawSeq),
+    Reply(Reply<'a>),
+}
+
+/// Reply to a query, which `Decoder::extract_event` takes note of rather than returns
+enum Reply<'a> {
+    Da1(Csi),
+    Da2(Csi),
+    XtVersion(&'a [u8]),
+    TermCap(&'a [u8]),
+    Other,
+}
+
+impl Decoder {
+    pub(crate) fn new(keys: KeyTable, tcap: ::terminfo::Merge) -> Self {
+        Self { mode: Mode::Esc, flags: Flags::empty(), keys, cpr_pending: 0, da_pending: false,
+               info: TerminalInfo::unknown, tcap, replied: false, long_str: None }
+    }
+
+    pub(crate) fn extract_event<A: Alloc>(&mut self, inbuf: &mut Ringbuffer<A>) -> Option<Event> {
+        let mut buf = [0; BUFFER_SIZE_MAX];
+        self.replied = false;
+        loop {
+            let nbytes = inbuf.read(&mut buf);
+            if 0 == nbytes { return None }
+
+            if let Some(mut esc) = self.long_str {
+                // ended by ST, or BEL as xterm allows
+                let end = buf[0..nbytes].iter().position(|&b| {
+                    let end = 0x07 == b || esc && b'\\' == b;
+                    esc = 0x1B == b;
+                    end
+                });
+                inbuf.skip(end.map_or(nbytes, |k| k + 1));
+                self.long_str = if end.is_some() { None } else { Some(esc) };
+                continue
+            }
+
+            let (tok, n) = self.decode(&buf[0..nbytes], self.mode)?;
+            match tok {
+                Token::LongStr(seq) => {
+                    inbuf.skip(n.get());
+                    self.long_str = Some(0x1B == buf[n.get() - 1]);
+                    return Some(Event::Unknown(seq))
+                },
+                Token::Event(ev) => {
+                    inbuf.skip(n.get());
+                    if let Event::CursorPos(..) = ev { self.cpr_pending = self.cpr_pending.saturating_sub(1) }
+                    return Some(ev)
+                },
+                Token::Reply(Reply::Da1(csi)) => { self.info.set_da1(csi.params()); self.da_pending = false },
+                Token::Reply(Reply::Da2(csi)) => self.info.set_da2(csi.params()),
+                Token::Reply(Reply::XtVersion(s)) => self.info.set_xtversion(s),
+                Token::Reply(Reply::TermCap(s)) => { self.tcap.merge(s); },
+                Token::Reply(Reply::Other) => (),
+            }
             inbuf.skip(n.get());
-            return Some((mod_, key))
+            self.replied = true;
+        }
+    }
+
+    /// Take a held escape, and whatever follows it, as the user is done typing.
+    pub(crate) fn flush_esc<A: Alloc>(&mut self, inbuf: &mut Ringbuffer<A>) -> Option<Event> {
+        if !is_esc_pending(inbuf) { return None }
+        let mut buf = [0; BUFFER_SIZE_MAX];
+        let nbytes = inbuf.read(&mut buf);
+        let (ev, n) = match self.decode(&buf[1..nbytes], Mode::Esc) {
+            Some((Token::Event(Event::Key(mod_, key)), n)) => (Event::Key(mod_ | Mod::Alt, key), n.get() + 1),
+            _ => (Event::Key(Mod::empty(), self.esc()), 1),
+        };
+        inbuf.skip(n);
+        Some(ev)
+    }
+
+    #[inline]
+    fn esc(&self) -> Key { if self.flags.contains(Flags::RawCtrl) { Key::Char('\x1B') } else { Key::Esc } }
+
+    fn decode<'a>(&self, buf: &'a [u8], mode: Mode) -> Option<(Token<'a>, NonZeroUsize)> {
+        let (&b0, rest) = buf.split_first()?;
+        let replies_pending = 0 != self.cpr_pending || self.da_pending;
+
+        match parse_csi(buf) {
+            Ok(csi) if 0 != self.cpr_pending && 0 == csi.private && b'R' == csi.final_ && 2 == csi.n_ps => {
+                let (y, x) = (csi.ps[0].saturating_sub(1), csi.ps[1].saturating_sub(1));
+                return Some((Token::Event(Event::CursorPos(x, y)), csi.len))
+            },
+            Ok(csi) if b'?' == csi.private && b'c' == csi.final_ => return Some((Token::Reply(Reply::Da1(csi)), csi.len)),
+            Ok(csi) if b'>' == csi.private && b'c' == csi.final_ => return Some((Token::Reply(Reply::Da2(csi)), csi.len)),
+            Err(true) if replies_pending => return None,
+            _ => (),
+        }
+
+        // else it could be alt-shift-p
+        if self.da_pending {
+            match parse_dcs(buf) {
+                Ok((s, n)) if s.starts_with(b">|") => return Some((Token::Reply(Reply::XtVersion(&s[2..])), n)),
+                Ok((s, n)) if s.starts_with(b"1+r") => return Some((Token::Reply(Reply::TermCap(s)), n)),
+                Ok((_, n)) => return Some((Token::Reply(Reply::Other), n)),
+                Err(true) => return None,
+                Err(false) => (),
+            }
+        }
+
+        // with raw control characters, e.g. `kbs` of `^?` is no backspace
+        if self.flags.contains(Flags::RawCtrl) && (b0 < 0x20 || 0x7F == b0) && 0x1B != b0 {
+            return Some((Token::Event(Event::Key(Mod::empty(), Key::Char(b0 as _))), one))
         }
 
-        // it's not escape sequence, so it's ALT or ESC; check mode
-        inbuf.skip(1);
-        return Some(match mode {
-            Mode::Esc => (Mod::empty(), Key::Char('\x1B')),
-            Mode::Alt => {
-                let (mod_, key) = extract_event(inbuf, mode, keys)?;
-                (mod_ | Mod::Alt, key)
+        // not all keys begin with escape, e.g. backspace
+        if let Some((mod_, key, n)) = parse_escape_seq(buf, &self.keys) { return Some((Token::Event(Event::Key(mod_, key)), n)) }
+
+        // a sequence of no key known, which must not leak into the input as characters
+        let seq = parse_seq(buf);
+        match seq {
+            Ok((n, false)) => return Some((Token::Event(Event::Unknown(RawSeq::new(&buf[0..n.get()]))), n)),
+            Ok((n, true)) => return Some((Token::LongStr(RawSeq::new(&buf[0..n.get()])), n)),
+            Err(_) => (),
+        }
+
+        if 0x1B == b0 {
+            // it's not escape sequence, so it's ALT or ESC; check mode
+            return match mode {
+                Mode::Esc => Some((Token::Event(Event::Key(Mod::empty(), self.esc())), one)),
+                Mode::Delay(_) if Err(true) == seq => None,
+                Mode::Delay(_) if self.keys.iter().any(|(seq, _)| seq.len() > buf.len() && seq.starts_with(buf)) => None,
+                Mode::Alt | Mode::Delay(_) => match self.decode(rest, mode)? {
+                    (Token::Event(Event::Key(mod_, key)), n) =>
+                        Some((Token::Event(Event::Key(mod_ | Mod::Alt, key)), NonZeroUsize::new(n.get() + 1)?)),
+                    _ => Some((Token::Event(Event::Key(Mod::empty(), self.esc())), one)),
+                },
+            }
+        }
+
+        if !self.flags.contains(Flags::RawCtrl) {
+            if let Some((mod_, key)) = parse_ctrl(b0) { return Some((Token::Event(Event::Key(mod_, key)), one)) }
+        }
+
+        // utf8; the rest of a character is awaited, but bytes of none are taken, so they do not
+        // block what follows
+        match ::utf8::decode(buf)? {
+            Decoded::Char(x, n) => Some((Token::Event(Event::Key(Mod::empty(), Key::Char(x))), n)),
+            Decoded::Partial => None,
+            Decoded::Invalid(_) if self.flags.contains(Flags::RawBytes) => Some((Token::Event(Event::Byte(b0)), one)),
+            Decoded::Invalid(n) => Some((Token::Event(Event::Key(Mod::empty(), Key::Char('\u{FFFD}'))), n)),
+        }
+    }
+}
+
+/// Whether the input begins with an escape, which `extract_event` may be holding back in case
+/// more follows
+pub(crate) fn is_esc_pending<A: Alloc>(inbuf: &Ringbuffer<A>) -> bool {
+    let mut b = [0; 1];
+    1 == inbuf.read(&mut b) && 0x1B == b[0]
+}
+
+const CSI_PARAMS_MAX: usize = 16;
+
+/// Control sequence, `CSI [private] params final`
+#[derive(Debug, Clone, Copy)]
+struct Csi {
+    private: u8,
+    ps: [u32; CSI_PARAMS_MAX],
+    n_ps: usize,
+    final_: u8,
+    len: NonZeroUsize,
+}
+
+impl Csi {
+    #[inline]
+    fn params(&self) -> &[u32] { &self.ps[0..self.n_ps] }
+}
+
+/// Parse a control sequence.
+///
+/// Fails with `true` if `buf` may be the beginning of one, else `false`.
+fn parse_csi(buf: &[u8]) -> Result<Csi, bool> {
+    if !buf.starts_with(b"\x1B[") { return Err(buf.len() > 1 && b"\x1B[".starts_with(buf)) }
+    let mut csi = Csi { private: 0, ps: [0; CSI_PARAMS_MAX], n_ps: 0, final_: 0, len: one };
+    let mut k = 2;
+    if let Some(&b @ b'<'..=b'?') = buf.get(k) { csi.private = b; k += 1 }
+    for (i, &b) in buf.iter().enumerate().skip(k) {
+        match b {
+            b'0'..=b'9' => {
+                if 0 == csi.n_ps { csi.n_ps = 1 }
+                let p = &mut csi.ps[csi.n_ps - 1];
+                *p = p.saturating_mul(10).saturating_add((b - b'0') as _);
+            },
+            b';' => {
+                if 0 == csi.n_ps { csi.n_ps = 1 }
+                if CSI_PARAMS_MAX == csi.n_ps { return Err(false) }
+                csi.n_ps += 1;
+            },
+            0x40..=0x7E => {
+                csi.final_ = b;
+                csi.len = NonZeroUsize::new(i + 1).ok_or(false)?;
+                return Ok(csi)
             },
-        })
+            _ => return Err(false),
+        }
     }
+    Err(true)
+}
 
-    // utf8
-    if let Some((x, n)) = ::utf8::decode(&buf[0..nbytes])
-               .and_then(|(x, n)| ::core::char::from_u32(x).map(|x| (x, n))) {
-        inbuf.skip(n.into());
-        return Some((Mod::empty(), Key::Char(x)))
+/// Parse a device control string, `DCS payload ST`, and return the payload.
+///
+/// Fails with `true` if `buf` may be the beginning of one, else `false`.
+fn parse_dcs(buf: &[u8]) -> Result<(&[u8], NonZeroUsize), bool> {
+    if !buf.starts_with(b"\x1BP") { return Err(buf.len() > 1 && b"\x1BP".starts_with(buf)) }
+    match buf.windows(2).position(|w| b"\x1B\\" == w) {
+        Some(k) => Ok((&buf[2..k], NonZeroUsize::new(k + 2).ok_or(false)?)),
+        None => Err(buf.len() < BUFFER_SIZE_MAX),
     }
+}
 
-    None
+/// Parse any escape sequence: a control sequence, `CSI params intermediates final`; a
+/// single shift, `SS3 char`; or a control string, e.g. `OSC string ST`. Return its length, and
+/// whether it is a control string too long to hold, which is cut off there.
+///
+/// Fails with `true` if `buf` may be the beginning of one, else `false`.
+fn parse_seq(buf: &[u8]) -> Result<(NonZeroUsize, bool), bool> {
+    let mut cut = false;
+    let n = match buf {
+        [0x1B] => return Err(true),
+        [0x1B, b'[', rest @ ..] => {
+            let k = rest.iter().position(|&b| !(0x30..=0x3F).contains(&b)).ok_or(true)?;
+            let l = rest[k..].iter().position(|&b| !(0x20..=0x2F).contains(&b)).ok_or(true)?;
+            if !(0x40..=0x7E).contains(&rest[k+l]) { return Err(false) }
+            k + l + 3
+        },
+        [0x1B, b'O'] => return Err(true),
+        [0x1B, b'O', 0x20..=0x7E, ..] => 3,
+        [0x1B, b']', ..] | [0x1B, b'P', ..] | [0x1B, b'X', ..] | [0x1B, b'^', ..] | [0x1B, b'_', ..] => {
+            // ended by ST, or BEL as xterm allows
+            match buf.iter().enumerate().skip(2).find(|&(k, &b)| 0x07 == b || buf[k-1..].starts_with(b"\x1B\\")) {
+                Some((k, _)) => k + 1,
+                None if buf.len() < BUFFER_SIZE_MAX => return Err(true),
+                None => { cut = true; buf.len() },
+            }
+        },
+        _ => return Err(false),
+    };
+    NonZeroUsize::new(n).map(|n| (n, cut)).ok_or(false)
+}
+
+pub(crate) const QUEUE_LEN: usize = 64;
+
+/// Events taken from the input but not yet returned, e.g. keys typed while awaiting a reply
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Queue {
+    evs: [Event; QUEUE_LEN],
+    begin: usize,
+    len: usize,
+}
+
+impl Queue {
+    pub(crate) const fn new() -> Self {
+        Self { evs: [Event::Resize(0, 0); QUEUE_LEN], begin: 0, len: 0 }
+    }
+
+    #[inline]
+    pub(crate) fn is_full(&self) -> bool { QUEUE_LEN == self.len }
+
+    /// Returns `false` if full.
+    pub(crate) fn push(&mut self, ev: Event) -> bool {
+        if QUEUE_LEN == self.len { return false }
+        self.evs[(self.begin + self.len) % QUEUE_LEN] = ev;
+        self.len += 1;
+        true
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Event> {
+        if 0 == self.len { return None }
+        let ev = self.evs[self.begin];
+        self.begin = (self.begin + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(ev)
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -71,23 +426,271 @@ pub enum Key {
     PgDn,
     Char(char),
     F(u16),
+    /// Key which the user defined with [`UI::register_key`](../struct.UI.html#method.register_key)
+    User(u32),
+    /// Shift-Tab
+    BackTab,
+    /// "Begin" key, often the center of the keypad
+    Begin,
+    /// Upper left of keypad
+    KpA1,
+    /// Upper right of keypad
+    KpA3,
+    /// Center of keypad
+    KpB2,
+    /// Lower left of keypad
+    KpC1,
+    /// Lower right of keypad
+    KpC3,
+    KpEnter,
 }
 
 impl Key {
+    /// The control character of `b`, e.g. `Char('\x03')` for `b'c'`, as reported only with
+    /// `Flags::RawCtrl` set
+    #[deprecated(note = "Ctrl+C is reported as `Event::Key(Mod::Ctrl, Key::Char('c'))` unless `Flags::RawCtrl` is set")]
     pub const fn Ctrl(b: u8) -> Self { Key::Char((b & !0x60) as _) }
 }
 
+pub const UNKNOWN_LEN_MAX: usize = 32;
+
+/// Bytes of an escape sequence of no key known, cut off at `UNKNOWN_LEN_MAX`
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct RawSeq {
+    bytes: [u8; UNKNOWN_LEN_MAX],
+    len: usize,
+}
+
+impl RawSeq {
+    fn new(bs: &[u8]) -> Self {
+        let len = ::core::cmp::min(bs.len(), UNKNOWN_LEN_MAX);
+        let mut bytes = [0; UNKNOWN_LEN_MAX];
+        bytes[0..len].copy_from_slice(&bs[0..len]);
+        Self { bytes, len }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] { &self.bytes[0..self.len] }
+}
+
+impl ::core::fmt::Debug for RawSeq {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        f.write_str("RawSeq(\"")?;
+        for &b in self.as_bytes() { write!(f, "{}", ::core::ascii::escape_default(b))? }
+        f.write_str("\")")
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Event {
     Key(Mod, Key),
     Resize(u32, u32),
+    /// Reply to [`UI::query_cursor_pos`](../struct.UI.html#method.query_cursor_pos) which came
+    /// too late: zero-based `(x, y)` of the cursor
+    CursorPos(u32, u32),
+    /// The program was continued after it was stopped, e.g. by Ctrl+Z; the UI was started again
+    /// and the screen repainted.
+    Resumed,
+    /// The terminal hung up, e.g. as its window was closed, so no more input will come.
+    Hangup,
+    /// A file [watched](../struct.UI.html#method.watch_fd) with this token is readable.
+    Ready(u64),
+    /// Some thread called [`Waker::wake`](../struct.Waker.html#method.wake) with this value.
+    User(u64),
+    /// The [timer](../struct.UI.html#method.set_timer) of this id fired.
+    Timer(u64),
+    /// An escape sequence of no key known, which is taken whole rather than as the keys it is
+    /// made of
+    Unknown(RawSeq),
+    /// A byte of invalid UTF-8, if `Flags::RawBytes` is set
+    Byte(u8),
 }
 
 bitflags! {
     pub struct Mod: u16 {
-        const Alt  = 1 << 1;
+        const Shift = 1 << 0;
+        const Alt   = 1 << 1;
+        const Ctrl  = 1 << 2;
     }
 }
 
+bitflags! {
+    /// Options of input decoding
+    pub struct Flags: u16 {
+        /// Report control characters as such, e.g. `Char('\r')` rather than `Enter` and
+        /// `Char('\x01')` rather than `(Ctrl, Char('a'))`.
+        const RawCtrl = 1 << 0;
+        /// Report Ctrl+Z as a key rather than stop the program.
+        const NoSuspend = 1 << 1;
+        /// Report bytes of invalid UTF-8 as `Event::Byte` rather than `Char('\u{FFFD}')`.
+        const RawBytes = 1 << 2;
+    }
+}
+
+/// How to take an escape which is not the beginning of a known sequence
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Mode { Esc, Alt }
+pub enum Mode {
+    /// Take it as `Key::Esc`.
+    Esc,
+    /// Take it as `Mod::Alt` on the following key.
+    Alt,
+    /// Take it as `Mod::Alt` on the following key, if that follows within the given span;
+    /// else as `Key::Esc`.
+    Delay(::time::Span),
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use default_allocator::Heap;
+    use self::std::vec;
+    use self::std::vec::Vec;
+    use terminfo::{Merge, Spec};
+    use super::*;
+
+    fn decoder() -> Decoder {
+        let mut keys = Spec::empty.keys;
+        keys[0] = str0_utf8!("\x1BOP");
+        keys[18] = str0_utf8!("\x1B[A");
+        keys[24] = str0_utf8!("\x1B[1;2R");
+        keys[TB_KEYS_NUM - 1] = str0_utf8!("\x7F");
+        Decoder::new(KeyTable::new(keys), Merge::new(Spec::empty, &mut []))
+    }
+
+    /// Take all the events of `bs`, and return them and the number of bytes left.
+    fn decode(d: &mut Decoder, inbuf: &mut Ringbuffer<Heap>, bs: &[u8]) -> (Vec<Event>, usize) {
+        assert!(inbuf.push(bs));
+        let mut evs = Vec::new();
+        while let Some(ev) = d.extract_event(inbuf) { evs.push(ev) }
+        (evs, inbuf.len())
+    }
+
+    fn decode_all(d: &mut Decoder, bs: &[u8]) -> Vec<Event> {
+        let (evs, n) = decode(d, &mut Ringbuffer::new_in(Heap), bs);
+        assert_eq!(0, n);
+        evs
+    }
+
+    fn key(key: Key) -> Event { Event::Key(Mod::empty(), key) }
+
+    fn chars(s: &str) -> Vec<Event> { s.chars().map(|c| key(Key::Char(c))).collect() }
+
+    #[test]
+    fn ctrl() {
+        let mut d = decoder();
+        assert_eq!(vec![Event::Key(Mod::Ctrl, Key::Char('a')), key(Key::Enter), key(Key::Tab),
+                        key(Key::Backspace), key(Key::Backspace), Event::Key(Mod::Ctrl, Key::Char(' ')),
+                        Event::Key(Mod::Ctrl, Key::Char('\\')), key(Key::Esc)],
+                   decode_all(&mut d, b"\x01\r\t\x08\x7F\x00\x1C\x1B"));
+
+        d.flags = Flags::RawCtrl;
+        // `kbs` too
+        assert_eq!(chars("\x01\r\t\x08\x7F\x00\x1C\x1B"), decode_all(&mut d, b"\x01\r\t\x08\x7F\x00\x1C\x1B"));
+        // yet not sequences
+        assert_eq!(vec![key(Key::Up)], decode_all(&mut d, b"\x1B[A"));
+    }
+
+    #[test]
+    fn held_esc() {
+        let mut d = decoder();
+        d.mode = Mode::Delay(::time::Span::from_ns(1));
+        let mut inbuf = Ringbuffer::new_in(Heap);
+        assert_eq!((vec![], 1), decode(&mut d, &mut inbuf, b"\x1B"));
+        assert!(is_esc_pending(&inbuf));
+        assert_eq!(Some(key(Key::Esc)), d.flush_esc(&mut inbuf));
+        assert!(inbuf.is_empty());
+
+        // the rest of a sequence comes in time
+        assert_eq!((vec![], 2), decode(&mut d, &mut inbuf, b"\x1BO"));
+        assert_eq!((vec![key(Key::F(1))], 0), decode(&mut d, &mut inbuf, b"P"));
+
+        // what follows comes too late
+        assert_eq!((vec![], 1), decode(&mut d, &mut inbuf, b"\x1B"));
+        assert_eq!(Some(key(Key::Esc)), d.flush_esc(&mut inbuf));
+        assert_eq!((chars("x"), 0), decode(&mut d, &mut inbuf, b"x"));
+    }
+
+    #[test]
+    fn alt() {
+        let mut d = decoder();
+        d.mode = Mode::Delay(::time::Span::from_ns(1));
+        assert_eq!(vec![Event::Key(Mod::Alt, Key::Char('x')), Event::Key(Mod::Alt | Mod::Ctrl, Key::Char('a')),
+                        Event::Key(Mod::Alt, Key::F(1))],
+                   decode_all(&mut d, b"\x1Bx\x1B\x01\x1B\x1BOP"));
+
+        d.mode = Mode::Alt;
+        assert_eq!(vec![Event::Key(Mod::Alt, Key::Char('x'))], decode_all(&mut d, b"\x1Bx"));
+        let mut inbuf = Ringbuffer::new_in(Heap);
+        assert_eq!((vec![], 1), decode(&mut d, &mut inbuf, b"\x1B"));
+        assert_eq!(Some(key(Key::Esc)), d.flush_esc(&mut inbuf));
+
+        d.mode = Mode::Esc;
+        assert_eq!(vec![key(Key::Esc), key(Key::Char('x'))], decode_all(&mut d, b"\x1Bx"));
+    }
+
+    #[test]
+    fn longest_match() {
+        let mut d = decoder();
+        assert!(d.keys.register(b"\x1BOPx", Mod::empty(), Key::User(1)));
+        assert_eq!(vec![key(Key::User(1)), key(Key::F(1))], decode_all(&mut d, b"\x1BOPx\x1BOP"));
+        // on a tie, that registered
+        assert!(d.keys.register(b"\x1B[A", Mod::Shift, Key::Up));
+        assert_eq!(vec![Event::Key(Mod::Shift, Key::Up)], decode_all(&mut d, b"\x1B[A"));
+    }
+
+    #[test]
+    fn cursor_pos() {
+        let mut d = decoder();
+        assert_eq!(vec![key(Key::F(15))], decode_all(&mut d, b"\x1B[1;2R"));
+        d.cpr_pending = 1;
+        assert_eq!(vec![Event::CursorPos(1, 0)], decode_all(&mut d, b"\x1B[1;2R"));
+        assert_eq!(0, d.cpr_pending);
+        assert_eq!(vec![key(Key::F(15))], decode_all(&mut d, b"\x1B[1;2R"));
+    }
+
+    #[test]
+    fn unknown() {
+        let mut d = decoder();
+        let unknown = |bs: &[u8]| Event::Unknown(RawSeq::new(bs));
+        assert_eq!(vec![unknown(b"\x1B[99;5~"), key(Key::Char('a'))], decode_all(&mut d, b"\x1B[99;5~a"));
+        assert_eq!(vec![unknown(b"\x1BOz"), key(Key::Char('a'))], decode_all(&mut d, b"\x1BOza"));
+        assert_eq!(vec![unknown(b"\x1B]11;rgb:0000/0000/0000\x1B\\"), unknown(b"\x1B]2;title\x07")],
+                   decode_all(&mut d, b"\x1B]11;rgb:0000/0000/0000\x1B\\\x1B]2;title\x07"));
+    }
+
+    #[test]
+    fn long_str() {
+        let mut d = decoder();
+        let mut bs = Vec::new();
+        bs.extend_from_slice(b"\x1B]");
+        bs.extend(::core::iter::repeat(b'x').take(300));
+        bs.extend_from_slice(b"\x07a");
+        let evs = decode_all(&mut d, &bs);
+        assert_eq!(vec![Event::Unknown(RawSeq::new(&bs)), key(Key::Char('a'))], evs);
+
+        // ST split between reads
+        bs.truncate(2 + 253);
+        bs.extend_from_slice(b"\x1B\\b");
+        assert_eq!(0x1B, bs[BUFFER_SIZE_MAX * 2 - 1]);
+        assert_eq!(vec![Event::Unknown(RawSeq::new(&bs)), key(Key::Char('b'))], decode_all(&mut d, &bs));
+
+        // not yet ended
+        let mut inbuf = Ringbuffer::new_in(Heap);
+        assert_eq!((vec![Event::Unknown(RawSeq::new(&bs))], 0), decode(&mut d, &mut inbuf, &bs[0..200]));
+        assert_eq!((vec![], 0), decode(&mut d, &mut inbuf, b"xxxx"));
+        assert_eq!((chars("c"), 0), decode(&mut d, &mut inbuf, b"\x1B\\c"));
+    }
+
+    #[test]
+    fn utf8() {
+        let mut d = decoder();
+        let mut inbuf = Ringbuffer::new_in(Heap);
+        // the rest of a character is awaited
+        assert_eq!((chars("é"), 2), decode(&mut d, &mut inbuf, b"\xC3\xA9\xE2\x82"));
+        assert_eq!((chars("€"), 0), decode(&mut d, &mut inbuf, b"\xAC"));
+        assert_eq!(chars("\u{FFFD}a\u{FFFD}("), decode_all(&mut d, b"\xFFa\xE2\x28"));
+        d.flags = Flags::RawBytes;
+        assert_eq!(vec![Event::Byte(0xFF), key(Key::Char('a'))], decode_all(&mut d, b"\xFFa"));
+    }
+}
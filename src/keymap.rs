@@ -0,0 +1,323 @@
+//! Key bindings
+//!
+//! A [`Keymap`](struct.Keymap.html) maps sequences of keys to actions. Sequences are written as
+//! in Emacs: keys separated by spaces, each key with any of the prefixes `C-` (control), `M-`
+//! (alt), and `S-` (shift), e.g. `"C-x C-s"`, `"M-<left>"`, or `"<F5>"`.
+
+use containers::collections::Vec;
+use core::fmt;
+use loca::Alloc;
+use time::{Point, Span};
+
+use input::{Key, Mod};
+
+/// Maximum number of keys in a bound sequence
+pub const SEQ_LEN_MAX: usize = 8;
+
+/// Key bindings, and the state of a sequence partly typed
+#[derive(Debug)]
+pub struct Keymap<T, A: Alloc> {
+    nodes: Vec<Node<T>, A>,
+    first: usize,
+    at: usize,
+    pending: [(Mod, Key); SEQ_LEN_MAX],
+    pending_len: usize,
+    deadline: Option<Point>,
+    timeout: Option<Span>,
+}
+
+/// Node of the trie of bound sequences; `child` and `next` are indices in `Keymap::nodes`, or
+/// `!0` if none.
+#[derive(Debug)]
+struct Node<T> {
+    key: (Mod, Key),
+    child: usize,
+    next: usize,
+    action: Option<T>,
+}
+
+/// Result of feeding a key to a [`Keymap`](struct.Keymap.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match<'a, T> {
+    /// The keys so far begin some bound sequence, so more are awaited.
+    Pending,
+    /// The keys so far are bound to this action.
+    Action(&'a T),
+    /// The keys so far are bound to nothing; here they are, to treat otherwise.
+    Unbound(&'a [(Mod, Key)]),
+}
+
+/// Error of [`Keymap::bind`](struct.Keymap.html#method.bind)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindErr {
+    /// The sequence is malformed.
+    Syntax,
+    /// The sequence is empty or longer than `SEQ_LEN_MAX`.
+    Length,
+    /// The sequence is a prefix of a bound sequence, or vice versa.
+    Conflict,
+    NoMemory,
+}
+
+impl<T, A: Alloc> Keymap<T, A> {
+    #[inline]
+    pub const fn new_in(alloc: A) -> Self { Self {
+        nodes: Vec::new_in(alloc),
+        first: !0, at: !0,
+        pending: [(Mod::empty(), Key::Esc); SEQ_LEN_MAX], pending_len: 0,
+        deadline: None, timeout: None,
+    } }
+
+    /// Bind the sequence `seq` to `action`, and return the action it was bound to before, if
+    /// any.
+    pub fn bind(&mut self, seq: &str, action: T) -> Result<Option<T>, BindErr> {
+        let mut keys = [(Mod::empty(), Key::Esc); SEQ_LEN_MAX];
+        let mut n = 0;
+        for s in seq.split_whitespace() {
+            *keys.get_mut(n).ok_or(BindErr::Length)? = parse_key(s).ok_or(BindErr::Syntax)?;
+            n += 1;
+        }
+        if 0 == n { return Err(BindErr::Length) }
+
+        let mut parent = !0;
+        for (k, &key) in keys[0..n].iter().enumerate() {
+            let first = if !0 == parent { self.first } else { self.nodes[parent].child };
+            if !0 != parent && self.nodes[parent].action.is_some() { return Err(BindErr::Conflict) }
+            parent = match self.find(first, key) {
+                Some(i) => i,
+                None => {
+                    let i = self.nodes.len();
+                    self.nodes.push(Node { key, child: !0, next: first, action: None })
+                        .map_err(|_| BindErr::NoMemory)?;
+                    if !0 == parent { self.first = i } else { self.nodes[parent].child = i }
+                    i
+                },
+            };
+            if k + 1 == n && !0 != self.nodes[parent].child { return Err(BindErr::Conflict) }
+        }
+        Ok(self.nodes[parent].action.replace(action))
+    }
+
+    fn find(&self, mut i: usize, key: (Mod, Key)) -> Option<usize> {
+        while !0 != i {
+            if key == self.nodes[i].key { return Some(i) }
+            i = self.nodes[i].next;
+        }
+        None
+    }
+
+    /// Feed the next key typed.
+    pub fn feed(&mut self, mod_: Mod, key: Key) -> Match<T> {
+        let first = if !0 == self.at { self.first } else { self.nodes[self.at].child };
+        if let Some(p) = self.pending.get_mut(self.pending_len) {
+            *p = (mod_, key);
+            self.pending_len += 1;
+        }
+        match self.find(first, (mod_, key)) {
+            None => {
+                let n = self.pending_len;
+                self.reset();
+                Match::Unbound(&self.pending[0..n])
+            },
+            Some(i) if !0 == self.nodes[i].child => {
+                self.reset();
+                self.nodes[i].action.as_ref().map_or(Match::Pending, Match::Action)
+            },
+            Some(i) => {
+                self.at = i;
+                self.deadline = self.timeout.map(|t| Point::now() + t);
+                Match::Pending
+            },
+        }
+    }
+
+    /// Give up the sequence partly typed, if the timeout has elapsed since its last key.
+    ///
+    /// Call this when [`pending_timeout`](#method.pending_timeout) has elapsed with no key typed.
+    pub fn expire(&mut self) -> Option<Match<T>> {
+        if !self.deadline.map_or(false, |t| Point::now() >= t) { return None }
+        let n = self.pending_len;
+        self.reset();
+        Some(Match::Unbound(&self.pending[0..n]))
+    }
+
+    /// Give up the sequence partly typed.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.at = !0;
+        self.pending_len = 0;
+        self.deadline = None;
+    }
+
+    /// Return the keys of the sequence partly typed, e.g. to show the user.
+    #[inline]
+    pub fn pending(&self) -> Seq { Seq(&self.pending[0..self.pending_len]) }
+
+    /// Return how much longer to wait for the next key of the sequence partly typed, if any,
+    /// e.g. to pass to [`UI::fetch_event`](../struct.UI.html#method.fetch_event).
+    #[inline]
+    pub fn pending_timeout(&self) -> Option<Span> {
+        self.deadline.map(|t| { let now = Point::now(); if t > now { t - now } else { Span::from_ns(0) } })
+    }
+
+    /// How long to wait for the next key of a sequence before giving it up; `None` to wait
+    /// forever, which is the default
+    #[inline]
+    pub fn timeout_mut(&mut self) -> &mut Option<Span> { &mut self.timeout }
+}
+
+/// Parse a single key, e.g. `"C-x"`, `"M-<left>"`, or `"<F5>"`.
+pub fn parse_key(mut s: &str) -> Option<(Mod, Key)> {
+    let mut mod_ = Mod::empty();
+    loop {
+        mod_ |= match s.as_bytes() {
+            [b'C', b'-', _, ..] => Mod::Ctrl,
+            [b'M', b'-', _, ..] => Mod::Alt,
+            [b'S', b'-', _, ..] => Mod::Shift,
+            _ => break,
+        };
+        s = &s[2..];
+    }
+
+    let key = if s.len() > 2 && s.starts_with('<') && s.ends_with('>') {
+        let name = &s[1..s.len()-1];
+        match names.iter().find(|&&(x, _)| x.eq_ignore_ascii_case(name)) {
+            Some(&(_, key)) => key,
+            None if name.starts_with(&['f', 'F'][..]) => Key::F(name[1..].parse().ok()?),
+            None => return None,
+        }
+    } else if let Some(&(_, key)) = short_names.iter().find(|&&(x, _)| x == s) { key } else {
+        let mut cs = s.chars();
+        match (cs.next(), cs.next()) {
+            (Some(x), None) => Key::Char(x),
+            _ => return None,
+        }
+    };
+    Some((mod_, key))
+}
+
+static short_names: [(&str, Key); 5] = [
+    ("SPC", Key::Char(' ')), ("TAB", Key::Tab), ("RET", Key::Enter), ("ESC", Key::Esc), ("DEL", Key::Backspace),
+];
+
+static names: [(&str, Key); 25] = { use self::Key::*; [
+    ("left", Left), ("right", Right), ("up", Up), ("down", Down),
+    ("home", Home), ("end", End), ("insert", Insert), ("delete", Delete),
+    ("pgup", PgUp), ("prior", PgUp), ("pgdn", PgDn), ("next", PgDn),
+    ("tab", Tab), ("backtab", BackTab), ("return", Enter), ("escape", Esc), ("backspace", Backspace),
+    ("begin", Begin), ("kp-a1", KpA1), ("kp-a3", KpA3), ("kp-b2", KpB2), ("kp-c1", KpC1), ("kp-c3", KpC3),
+    ("kp-enter", KpEnter), ("space", Char(' ')),
+] };
+
+/// Sequence of keys, which displays in the syntax `Keymap::bind` takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seq<'a>(pub &'a [(Mod, Key)]);
+
+impl<'a> fmt::Display for Seq<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (k, &(mod_, key)) in self.0.iter().enumerate() {
+            if 0 != k { f.write_str(" ")? }
+            if mod_.contains(Mod::Ctrl)  { f.write_str("C-")? }
+            if mod_.contains(Mod::Alt)   { f.write_str("M-")? }
+            if mod_.contains(Mod::Shift) { f.write_str("S-")? }
+            match (short_names.iter().find(|&&(_, x)| x == key), names.iter().find(|&&(_, x)| x == key), key) {
+                (Some(&(s, _)), _, _) => f.write_str(s)?,
+                (None, Some(&(s, _)), _) => write!(f, "<{}>", s)?,
+                (None, None, Key::F(n)) => write!(f, "<f{}>", n)?,
+                (None, None, Key::Char(x)) => write!(f, "{}", x)?,
+                (None, None, _) => write!(f, "<{:?}>", key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use default_allocator::Heap;
+    use self::std::string::ToString;
+    use self::std::vec::Vec;
+    use super::*;
+
+    fn keymap() -> Keymap<u32, Heap> {
+        let mut km = Keymap::new_in(Heap);
+        assert_eq!(Ok(None), km.bind("C-x C-s", 0));
+        assert_eq!(Ok(None), km.bind("C-x C-f", 1));
+        assert_eq!(Ok(None), km.bind("<F5>", 2));
+        km
+    }
+
+    fn key(mod_: Mod, c: char) -> (Mod, Key) { (mod_, Key::Char(c)) }
+
+    #[test]
+    fn bind() {
+        let mut km = keymap();
+        assert_eq!(Ok(Some(2)), km.bind("<f5>", 3));
+        // a prefix of a bound sequence, and an extension of one
+        assert_eq!(Err(BindErr::Conflict), km.bind("C-x", 4));
+        assert_eq!(Err(BindErr::Conflict), km.bind("<F5> a", 4));
+        assert_eq!(Err(BindErr::Length), km.bind(" ", 4));
+        assert_eq!(Err(BindErr::Length), km.bind("a b c d e f g h i", 4));
+        assert_eq!(Ok(None), km.bind("a b c d e f g h", 4));
+        assert_eq!(Err(BindErr::Syntax), km.bind("C-x ab", 4));
+        assert_eq!(Err(BindErr::Syntax), km.bind("<nokey>", 4));
+        assert_eq!(Err(BindErr::Syntax), km.bind("C-", 4));
+
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert_eq!(Match::Action(&1), km.feed(Mod::Ctrl, Key::Char('f')));
+        assert_eq!(Match::Action(&3), km.feed(Mod::empty(), Key::F(5)));
+    }
+
+    #[test]
+    fn feed() {
+        let mut km = keymap();
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert_eq!("C-x", km.pending().to_string());
+        assert_eq!(Match::Action(&0), km.feed(Mod::Ctrl, Key::Char('s')));
+        assert_eq!("", km.pending().to_string());
+        assert_eq!(Match::Action(&2), km.feed(Mod::empty(), Key::F(5)));
+        assert_eq!(Match::Unbound(&[key(Mod::empty(), 'q')][..]), km.feed(Mod::empty(), Key::Char('q')));
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert_eq!(Match::Unbound(&[key(Mod::Ctrl, 'x'), key(Mod::empty(), 's')][..]),
+                   km.feed(Mod::empty(), Key::Char('s')));
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert_eq!(Match::Action(&0), km.feed(Mod::Ctrl, Key::Char('s')));
+    }
+
+    #[test]
+    fn expire() {
+        let mut km = keymap();
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        // with no timeout, it waits forever
+        assert_eq!(None, km.pending_timeout());
+        assert_eq!(None, km.expire());
+        km.reset();
+
+        *km.timeout_mut() = Some(Span::from_ns(3_600_000_000_000));
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert!(km.pending_timeout().map_or(false, |t| t > Span::from_ns(0)));
+        assert_eq!(None, km.expire());
+        km.reset();
+
+        *km.timeout_mut() = Some(Span::from_ns(0));
+        assert_eq!(Match::Pending, km.feed(Mod::Ctrl, Key::Char('x')));
+        assert_eq!(Some(Span::from_ns(0)), km.pending_timeout());
+        assert_eq!(Some(Match::Unbound(&[key(Mod::Ctrl, 'x')][..])), km.expire());
+        assert_eq!(None, km.expire());
+        assert_eq!(None, km.pending_timeout());
+        assert_eq!(Match::Action(&2), km.feed(Mod::empty(), Key::F(5)));
+    }
+
+    #[test]
+    fn display() {
+        for &(s, t) in &[("C-x C-s", "C-x C-s"), ("M-<left>", "M-<left>"), ("S-M-C-<F12>", "C-M-S-<f12>"),
+                         ("SPC TAB RET ESC DEL", "SPC TAB RET ESC DEL"), ("<space> <Tab> <escape>", "SPC TAB ESC"),
+                         ("<prior> <kp-enter> a \\", "<pgup> <kp-enter> a \\")] {
+            let keys: Vec<_> = s.split(' ').map(|s| parse_key(s).unwrap()).collect();
+            assert_eq!(t, Seq(&keys).to_string());
+            assert_eq!(keys, t.split(' ').map(|s| parse_key(s).unwrap()).collect::<Vec<_>>());
+        }
+    }
+}
@@ -16,12 +16,16 @@ extern crate buf;
 extern crate bitflags;
 extern crate chain;
 extern crate containers;
+#[cfg(test)]
+extern crate default_allocator;
 extern crate io;
 extern crate libc;
 extern crate loca;
 #[macro_use]
 extern crate null_terminated as nul;
 extern crate ptr;
+#[cfg(feature = "std")]
+extern crate std;
 extern crate subslice;
 #[macro_use]
 extern crate syscall;
@@ -32,11 +36,11 @@ extern crate unix_signal;
 extern crate unix_tty;
 
 use containers::collections::{RawVec, FixedStorage};
-use core::{cmp, fmt, mem::{self, MaybeUninit}, num::NonZeroUsize, sync::atomic::{AtomicBool, Ordering as Memord}};
+use core::{fmt, mem::{self, MaybeUninit}, num::NonZeroUsize, sync::atomic::{AtomicBool, AtomicI32, Ordering as Memord}};
 use io::Write;
 use libc::c_int;
 use loca::Alloc;
-use nul::NulStr;
+use nul::{Nul, NulStr};
 use unix::{file::File, err::OsErr};
 use unix_tty::TtyExt;
 
@@ -70,17 +74,84 @@ bitflags! {
 pub mod input;
 pub use input::{Event, Key, Mod};
 
+pub mod keymap;
+
+pub mod query;
+pub use query::TerminalInfo;
+
+pub mod record;
+
+pub mod tparm;
+
 mod cellbuf;
-mod ringbuffer;
+mod restore;
 mod term;
 mod terminfo;
+mod timer;
 mod utf8;
 mod util;
 
 pub use cellbuf::{CellBuf, CellsMut};
-use ringbuffer::Ringbuffer;
+pub use terminfo::TermInfo;
+pub mod ringbuffer;
+pub use ringbuffer::Ringbuffer;
+
+/// Self-pipe by which signal handlers and wakers tell `fetch_event` of the signals and
+/// wake-ups, in messages of `[signal, 0]` or `[0, value]`
+///
+/// It is made by the first `UI` and kept open for the life of the process, so a `Waker` never
+/// writes to an fd which was closed and maybe reused.
+static signal_rx: AtomicI32 = AtomicI32::new(-1);
+static signal_tx: AtomicI32 = AtomicI32::new(-1);
+
+static handled_signals: [c_int; 4] = [::libc::SIGWINCH, ::libc::SIGTSTP, ::libc::SIGCONT, ::libc::SIGHUP];
+
+unsafe extern "C" fn signal_handler(sig: usize, _: &::libc::siginfo_t, _: &::libc::ucontext_t) {
+    syscall!(WRITE, signal_tx.load(Memord::Relaxed), &[sig as u64, 0] as *const u64, 16);
+}
+
+/// Handle by which any thread can wake [`UI::fetch_event`](struct.UI.html#method.fetch_event),
+/// e.g. to redraw once some work is done
+#[derive(Debug, Clone, Copy)]
+pub struct Waker(());
+
+impl Waker {
+    /// Make `fetch_event` return `Event::User(value)`.
+    ///
+    /// Fails with `EAGAIN` if too many wake-ups are pending. Wake-ups after the `UI` is dropped
+    /// are discarded.
+    #[inline]
+    pub fn wake(&self, value: u64) -> Result<(), OsErr> { unsafe {
+        esyscall!(WRITE, signal_tx.load(Memord::Acquire), &[0, value] as *const u64, 16)?;
+        Ok(())
+    } }
+}
 
-static mut winch_fds: [c_int; 2] = [-1; 2];
+/// Set the handling of `sig` to the default.
+unsafe fn signal_default(sig: c_int) -> Result<(), OsErr> {
+    // `struct sigaction` as the kernel takes it: handler, flags, restorer, mask
+    let act: [usize; 4] = [0; 4];
+    esyscall!(RT_SIGACTION, sig, &act as *const _, 0, mem::size_of::<usize>())?;
+    Ok(())
+}
+
+static speeds: [(::libc::speed_t, u32); 30] = [
+    (::libc::B50, 50), (::libc::B75, 75), (::libc::B110, 110), (::libc::B134, 134), (::libc::B150, 150),
+    (::libc::B200, 200), (::libc::B300, 300), (::libc::B600, 600), (::libc::B1200, 1200),
+    (::libc::B1800, 1800), (::libc::B2400, 2400), (::libc::B4800, 4800), (::libc::B9600, 9600),
+    (::libc::B19200, 19200), (::libc::B38400, 38400), (::libc::B57600, 57600),
+    (::libc::B115200, 115200), (::libc::B230400, 230400), (::libc::B460800, 460800),
+    (::libc::B500000, 500000), (::libc::B576000, 576000), (::libc::B921600, 921600),
+    (::libc::B1000000, 1000000), (::libc::B1152000, 1152000), (::libc::B1500000, 1500000),
+    (::libc::B2000000, 2000000), (::libc::B2500000, 2500000), (::libc::B3000000, 3000000),
+    (::libc::B3500000, 3500000), (::libc::B4000000, 4000000),
+];
+
+/// Return the output line speed per `tios`, in bits per second, or 0 if unknown or hung up.
+fn baud_rate(tios: &::libc::termios) -> u32 {
+    let code = tios.c_cflag & ::libc::CBAUD;
+    speeds.iter().find(|&&(b, _)| code == b as _).map_or(0, |&(_, n)| n)
+}
 
 /// Cell-grid TTY UI
 ///
@@ -93,11 +164,30 @@ pub struct UI<A: Alloc> {
     fg: Attr, bg: Attr,
     term_size: (u16, u16),
     orig_tios: ::libc::termios,
-    input_mode: input::Mode,
-    inbuf: Ringbuffer<'static>,
-    keys: [&'static NulStr; input::TB_KEYS_NUM],
+    decoder: input::Decoder,
+    queue: input::Queue,
+    inbuf: Ringbuffer<A>,
+    /// When to take a held escape as a key, in `Mode::Delay`
+    esc_deadline: Option<::time::Point>,
+    /// When to stop waiting for late replies to `query_cursor_pos`
+    cpr_deadline: Option<::time::Point>,
+    /// Files to watch, with their tokens
+    watches: [(c_int, u64); WATCHES_MAX],
+    n_watches: usize,
+    /// Which watch to check first, so one always readable does not starve the others
+    watch_next: usize,
+    timers: timer::Timers,
+    recorder: Option<record::Recorder>,
+    /// Whether the terminal hung up, so it is read no more
+    hung_up: bool,
 }
 
+/// Maximum number of files which `fetch_event` can watch besides the TTY
+pub const WATCHES_MAX: usize = 16;
+
+/// Maximum number of timers which can be set at once
+pub const TIMERS_MAX: usize = 16;
+
 static lock: AtomicBool = AtomicBool::new(false);
 
 macro_rules! static_buf {
@@ -108,24 +198,33 @@ macro_rules! static_buf {
 }
 
 impl<A: Alloc> UI<A> {
-    /// Open "/dev/tty" and make a new `UI` with it.
-    pub fn new_in(alloc: A) -> Result<Self, OsErr> {
+    /// Open "/dev/tty" and make a new `UI` with it. The input buffer and the cells are both
+    /// allocated in `alloc`, so it must be `Clone`.
+    pub fn new_in(alloc: A) -> Result<Self, OsErr> where A: Clone {
         use unix::file::*;
 
+        let inbuf = Ringbuffer::with_capacity_in(alloc.clone(), 0x1000).ok_or(OsErr::ENOMEM)?;
+
         lock.compare_exchange(false, true, Memord::Acquire, Memord::Relaxed)
             .map_err(|_| OsErr::EBUSY)?;
 
         let tty = open_at(None, str0!("/dev/tty"), OpenMode::RdWr, None)?;
-        let terminfo::Spec { funcs, keys } = terminfo::init(unsafe { static_buf![u8; 0x4000] })
-            .ok_or(OsErr(unsafe { NonZeroUsize::new_unchecked(!0) }))?;
-        let (winch_rx, winch_tx) = new_pipe(OpenFlags::empty())?;
-        unsafe { winch_fds = [winch_rx.fd() as i32, winch_tx.fd() as i32] };
-        mem::forget((winch_rx, winch_tx));
-
-        unsafe extern "C" fn sigwinch_handler(_: usize, _: &::libc::siginfo_t, _: &::libc::ucontext_t) {
-            syscall!(WRITE, winch_fds[1], &1u32 as *const u32, 4);
+        let spec = terminfo::load().ok_or(OsErr(unsafe { NonZeroUsize::new_unchecked(!0) }))?;
+        let terminfo::Spec { funcs, keys } = spec;
+        if signal_tx.load(Memord::Acquire) < 0 {
+            let (rx, tx) = new_pipe(OpenFlags::O_NONBLOCK)?;
+            signal_rx.store(rx.fd() as _, Memord::Relaxed);
+            signal_tx.store(tx.fd() as _, Memord::Release);
+            mem::forget((rx, tx));
+        } else {
+            // drop what was sent to a former `UI`
+            let mut msg = [0u64; 2];
+            while let Ok(16) = unsafe { esyscall!(READ, signal_rx.load(Memord::Relaxed), &mut msg as *mut u64, 16) } {}
+        }
+
+        for &sig in &handled_signals {
+            unsafe { unix_signal::sigaction(sig as _, signal_handler, unix_signal::Flags::empty())?; }
         }
-        unsafe { unix_signal::sigaction(::libc::SIGWINCH as _, sigwinch_handler, unix_signal::Flags::empty())?; }
 
         let orig_tios = tty.get_termios()?;
         let mut ui = Self {
@@ -138,14 +237,20 @@ impl<A: Alloc> UI<A> {
             fg: Attr::Default, bg: Attr::Default,
             term_size: (0, 0),
             orig_tios,
-            input_mode: input::Mode::Esc,
-            inbuf: Ringbuffer {
-                buf: unsafe { static_buf![u8; 0x1000] },
-                begin: 0 as _, end: 0 as _,
-            },
-            keys,
+            decoder: input::Decoder::new(input::KeyTable::new(keys),
+                                         terminfo::Merge::new(spec, unsafe { static_buf![u8; 0x1000] })),
+            queue: input::Queue::new(),
+            inbuf,
+            esc_deadline: None,
+            cpr_deadline: None,
+            watches: [(-1, 0); WATCHES_MAX], n_watches: 0, watch_next: 0,
+            timers: timer::Timers::new(),
+            recorder: None,
+            hung_up: false,
         };
         ui.term_writer.funcs = funcs;
+        ui.term_writer.padding = terminfo::load_info()
+            .map_or(tparm::Padding::none, |info| tparm::Padding::new(&info, baud_rate(&ui.orig_tios)));
         ui.start()?;
         ui.term_writer.write_clear(ui.cursor_x, ui.cursor_y, ui.fg, ui.bg);
         Ok(ui)
@@ -184,20 +289,46 @@ impl<A: Alloc> UI<A> {
 
         self.update_size()?;
         self.inbuf.clear();
+        if restore::is_enabled() { self.arm_restore() }
+        Ok(())
+    }
+
+    /// Sequences which put the terminal out of the UI, as `stop` writes
+    fn exit_seq(&self) -> [&'static NulStr; 5] {
+        use term::Func::*;
+        let funcs = &self.term_writer.funcs;
+        [funcs[ShowCursor as usize], funcs[Sgr0 as usize], funcs[ClearScreen as usize],
+         funcs[ExitCa as usize], funcs[ExitKeypad as usize]]
+    }
+
+    fn arm_restore(&mut self) {
+        let mut bs = [0; restore::SEQ_LEN_MAX];
+        let mut buf = tparm::Buf::new(&mut bs);
+        // no padding, which could overflow the buffer, and is moot as the program is dying
+        for seq in &self.exit_seq() { let _ = tparm::tputs(&mut buf, &seq[..], &tparm::Padding::none); }
+        let fd = self.tty_mut().fd();
+        restore::arm(fd as _, self.orig_tios, buf.as_bytes());
+    }
+
+    /// Restore the terminal if the program is killed by a fatal signal, e.g. SIGTERM or SIGSEGV,
+    /// or, with the "std" feature, if it panics, while the UI is started.
+    ///
+    /// SIGHUP is handled so too, so it is no longer returned as `Event::Hangup`.
+    ///
+    /// This is process-wide, and lasts until the `UI` is dropped. The signals are then handled
+    /// by default again, rather than as they were before.
+    pub fn restore_on_fatal(&mut self) -> Result<(), OsErr> {
+        unsafe { restore::enable()? };
+        self.arm_restore();
         Ok(())
     }
 
     /// Stop the UI temporarily and revert the term to its initial state.
     pub fn stop(&mut self) {
-        use core::fmt::Write;
-        use term::Func::*;
-        write!(&mut self.term_writer.w, "{}{}{}{}{}",
-               self.term_writer.funcs[ShowCursor  as usize],
-               self.term_writer.funcs[Sgr0        as usize],
-               self.term_writer.funcs[ClearScreen as usize],
-               self.term_writer.funcs[ExitCa      as usize],
-               self.term_writer.funcs[ExitKeypad  as usize],
-              );
+        restore::disarm();
+        for seq in &self.exit_seq() {
+            let _ = tparm::tputs(&mut self.term_writer.w, &seq[..], &self.term_writer.padding);
+        }
         self.term_writer.w.flush();
         let tios = self.orig_tios;
         self.tty_mut().set_termios(tios, ::unix_tty::termios::When::Flush);
@@ -207,6 +338,7 @@ impl<A: Alloc> UI<A> {
         self.term_size = self.tty_mut().get_tty_size()?;
         self.cell_buffer.resize(self.term_size.0 as _, self.term_size.1 as _).map_err(|_| OsErr::ENOMEM)?;
         self.cell_buffer.cells_mut().0.clear(self.fg, self.bg);
+        self.term_writer.write_scroll_region(0, (self.term_size.1 as usize).saturating_sub(1));
         self.term_writer.write_clear(self.cursor_x, self.cursor_y, self.fg, self.bg);
         self.term_writer.w.flush();
         Ok(())
@@ -233,48 +365,386 @@ impl<A: Alloc> UI<A> {
             }
         }
         if !term::is_cursor_hidden(self.cursor_x as _, self.cursor_y as _) {
-            term::write_cursor(&mut self.term_writer.w, self.cursor_x as _, self.cursor_y as _);
+            self.term_writer.write_cursor(self.cursor_x as _, self.cursor_y as _);
         }
         self.term_writer.w.flush();
     }
 
+    /// Take the next event from the input buffer, or `Some(None)` if only replies to queries.
+    #[inline]
+    fn extract_event(&mut self) -> Option<Option<Event>> {
+        // Past this, `CSI 1;<mod> R` is taken as a key again.
+        if self.cpr_deadline.map_or(false, |t| t <= ::time::Point::now()) {
+            self.cpr_deadline = None;
+            self.decoder.cpr_pending = 0;
+        }
+        let n = self.inbuf.len();
+        let ev = self.decoder.extract_event(&mut self.inbuf);
+        // any escape held is taken, or another is held, whose wait starts over.
+        if n != self.inbuf.len() { self.esc_deadline = None }
+        match ev {
+            Some(ev) => Some(Some(ev)),
+            None if self.decoder.replied => Some(None),
+            None => None,
+        }
+    }
+
     /// Fetch the next event from the TTY.
+    ///
+    /// Returns `Ok(None)` if the timeout elapses first, or a late reply to a query comes rather
+    /// than an event.
+    ///
+    /// Unless `Flags::NoSuspend` is set, Ctrl+Z stops the program as it would in cooked mode;
+    /// once it is continued, `Event::Resumed` is returned.
+    ///
+    /// When the terminal hangs up, `Event::Hangup` is returned once. The TTY is then read no
+    /// more, but timers, watched files and wake-ups are still reported.
+    pub fn fetch_event(&mut self, timeout: Option<::time::Span>) -> Result<Option<input::Event>, OsErr> {
+        loop {
+            let ev = match self.queue.pop() { Some(ev) => Some(ev), None => self.read_event(timeout)? };
+            match ev {
+                // as `Flags::RawCtrl` reports it, too
+                Some(Event::Key(mod_, key))
+                    if ((Mod::Ctrl, Key::Char('z')) == (mod_, key) || (Mod::empty(), Key::Char('\x1A')) == (mod_, key)) &&
+                       !self.decoder.flags.contains(input::Flags::NoSuspend) => self.suspend()?,
+                ev => return Ok(ev),
+            }
+        }
+    }
+
+    /// Stop the UI and then the program, as by SIGTSTP with its default handling.
+    ///
+    /// Once the program is continued, SIGCONT comes through the self-pipe, and then
+    /// `read_event` restarts the UI.
+    fn suspend(&mut self) -> Result<(), OsErr> { unsafe {
+        self.stop();
+        signal_default(::libc::SIGTSTP)?;
+        syscall!(KILL, syscall!(GETPID), ::libc::SIGTSTP);
+        unix_signal::sigaction(::libc::SIGTSTP as _, signal_handler, unix_signal::Flags::empty())?;
+        Ok(())
+    } }
+
+    /// Take note that the terminal hung up, and return `Event::Hangup` unless that was already
+    /// returned.
+    fn hangup(&mut self) -> Option<Event> {
+        if mem::replace(&mut self.hung_up, true) { None } else { Some(Event::Hangup) }
+    }
+
+    /// Take the next event without waiting, e.g. once `tty_fd` or `signal_fd` is readable in an
+    /// external event loop.
+    ///
+    /// Returns `Ok(None)` once there is nothing more to take; then wait until either fd is
+    /// readable, or at most [`poll_timeout`](#method.poll_timeout), and call this again.
+    pub fn try_event(&mut self) -> Result<Option<input::Event>, OsErr> {
+        loop {
+            let ev = self.fetch_event(Some(::time::Span::from_ns(0)))?;
+            if ev.is_some() || !self.decoder.replied { return Ok(ev) }
+        }
+    }
+
+    /// Watch the file `fd` besides the TTY, so `fetch_event` returns `Event::Ready(token)` while
+    /// it is readable. If `fd` is already watched, its token is replaced.
+    ///
+    /// Fails with `ENOSPC` if `WATCHES_MAX` files are already watched.
+    pub fn watch_fd(&mut self, fd: c_int, token: u64) -> Result<(), OsErr> {
+        let n = self.n_watches;
+        match self.watches[0..n].iter_mut().find(|w| fd == w.0) {
+            Some(w) => w.1 = token,
+            None => {
+                *self.watches.get_mut(n).ok_or(OsErr::ENOSPC)? = (fd, token);
+                self.n_watches += 1;
+            },
+        }
+        Ok(())
+    }
+
+    /// Watch the file `fd` no longer. Returns whether it was watched.
+    pub fn unwatch_fd(&mut self, fd: c_int) -> bool {
+        let n = self.n_watches;
+        match self.watches[0..n].iter().position(|w| fd == w.0) {
+            Some(k) => {
+                self.watches[k..n].rotate_left(1);
+                self.n_watches -= 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Read what input is available, and record it if recording.
+    fn fill_inbuf(&mut self) -> Result<usize, OsErr> {
+        let n = self.inbuf.push_from_file(self.term_writer.w.as_mut())?;
+        if let (Some(r), true) = (self.recorder.as_mut(), 0 != n) {
+            // what was read is at the end
+            let (xs, ys) = self.inbuf.as_slices();
+            let k = n.saturating_sub(ys.len());
+            r.input([&xs[xs.len() - k..], &ys[ys.len() - (n - k)..]])?;
+        }
+        Ok(n)
+    }
+
+    /// Record the input to `file`, in the [format](record/index.html#format) which a
+    /// [`Player`](record/struct.Player.html) plays, beginning with the present size.
+    ///
+    /// Replies to queries, e.g. by `probe_terminal`, are recorded too, but a `Player` awaits none,
+    /// so rather record after any such queries. Replay in the same input mode.
+    pub fn start_recording(&mut self, file: File) -> Result<(), OsErr> {
+        let mut r = record::Recorder::new(file)?;
+        r.resize(self.term_size.0, self.term_size.1)?;
+        self.recorder = Some(r);
+        Ok(())
+    }
+
+    /// Stop recording, and return the file recorded to, if any.
     #[inline]
-    pub fn fetch_event(&mut self, timeout: Option<::time::Span>) -> Result<Option<input::Event>, OsErr> { unsafe {
-        let tty_fd = self.tty_mut().fd() as ::libc::c_int;
+    pub fn stop_recording(&mut self) -> Option<File> { self.recorder.take().map(record::Recorder::into_file) }
+
+    /// Set timer `id` to fire after `delay`, and then every `period` if any, so `fetch_event`
+    /// returns `Event::Timer(id)`, replacing any timer of the same `id`.
+    ///
+    /// Fails with `ENOSPC` if `TIMERS_MAX` timers are already set.
+    #[inline]
+    pub fn set_timer(&mut self, id: u64, delay: ::time::Span, period: Option<::time::Span>) -> Result<(), OsErr> {
+        if self.timers.set(id, delay, period) { Ok(()) } else { Err(OsErr::ENOSPC) }
+    }
+
+    /// Cancel timer `id`. Returns whether it was set.
+    #[inline]
+    pub fn cancel_timer(&mut self, id: u64) -> bool { self.timers.cancel(id) }
+
+    /// Return a handle by which other threads can wake `fetch_event`.
+    #[inline]
+    pub fn waker(&self) -> Waker { Waker(()) }
+
+    /// Return the fd of the TTY, to wait on in an external event loop.
+    #[inline]
+    pub fn tty_fd(&self) -> c_int { self.term_writer.w.as_ref().fd() as _ }
+
+    /// Return the fd on which signals, e.g. SIGWINCH, and wake-ups are told, to wait on in an
+    /// external event loop.
+    #[inline]
+    pub fn signal_fd(&self) -> c_int { signal_rx.load(Memord::Relaxed) }
 
-        use input::extract_event;
+    /// Return how long an external event loop may wait on `tty_fd` and `signal_fd` before it
+    /// must call `try_event` anyway, or `None` if it may wait forever.
+    #[inline]
+    pub fn poll_timeout(&mut self) -> Option<::time::Span> {
+        let deadline = match (self.esc_deadline(), self.timers.next_deadline()) {
+            (Some(s), Some(t)) => Some(::core::cmp::min(s, t)),
+            (s, t) => s.or(t),
+        };
+        deadline.map(|t| {
+            let now = ::time::Point::now();
+            if t > now { t - now } else { ::time::Span::from_ns(0) }
+        })
+    }
+
+    /// Return when to take a held escape as a key, if any is held.
+    fn esc_deadline(&mut self) -> Option<::time::Point> {
+        match self.decoder.mode {
+            input::Mode::Delay(d) if input::is_esc_pending(&self.inbuf) =>
+                Some(*self.esc_deadline.get_or_insert_with(|| ::time::Point::now() + d)),
+            _ => { self.esc_deadline = None; None },
+        }
+    }
+
+    /// Fetch the next event from the TTY, not from the queue.
+    fn read_event(&mut self, timeout: Option<::time::Span>) -> Result<Option<input::Event>, OsErr> { unsafe {
+        let tty_fd = self.tty_mut().fd() as ::libc::c_int;
 
-        if let Some((mod_, key)) = extract_event(&mut self.inbuf, self.input_mode, mem::transmute(self.keys)) { return Ok(Some(Event::Key(mod_, key))) }
+        if let Some(ev) = self.extract_event() { return Ok(ev) }
 
-        match self.inbuf.push_from_file(self.term_writer.w.as_mut()) {
+        if !self.hung_up { match self.fill_inbuf() {
             Err(OsErr::EAGAIN) | Err(OsErr::EWOULDBLOCK) => return Ok(None),
+            Err(OsErr::EIO) => return Ok(self.hangup()),
             Err(e) => return Err(e),
-            Ok(n) if n > 0 => if let Some((mod_, key)) = extract_event(&mut self.inbuf, self.input_mode, mem::transmute(self.keys)) { return Ok(Some(Event::Key(mod_, key))) },
+            Ok(n) if n > 0 => if let Some(ev) = self.extract_event() { return Ok(ev) },
             _ => ()
-        }
+        } }
 
         // 0 == r || not enough data
+        // the deadline is on the monotonic clock, so the whole timeout holds across interruptions
+        let deadline = timeout.map(|t| ::time::Point::now() + t);
         loop {
-            let mut events = mem::zeroed();
-            ::libc::FD_SET(tty_fd, &mut events);
-            ::libc::FD_SET(winch_fds[0], &mut events);
-            esyscall!(SELECT, cmp::max(tty_fd, winch_fds[0])+1, &mut events as *mut _, 0, 0,
-                      timeout.and_then(::time::Span::to_c_timespec)
-                             .map(|::libc::timespec { tv_sec, tv_nsec }| ::libc::timeval { tv_sec, tv_usec: (tv_nsec / 1000) as _ })
-                             .as_mut().map_or(0 as *const ::libc::timeval, |p| p as *const _))?;
-            if ::libc::FD_ISSET(tty_fd, &events as *const _ as *mut _) {
-                if 0 == self.inbuf.push_from_file(self.term_writer.w.as_mut())? { continue }
-                if let Some((mod_, key)) = extract_event(&mut self.inbuf, self.input_mode, mem::transmute(self.keys)) { return Ok(Some(Event::Key(mod_, key))) }
+            let remaining = deadline.map(|t| {
+                let now = ::time::Point::now();
+                if t > now { t - now } else { ::time::Span::from_ns(0) }
+            });
+
+            if let Some(id) = self.timers.fire() { return Ok(Some(Event::Timer(id))) }
+
+            // an escape is held back only so long, in case it is the beginning of a sequence, and
+            // the wait is cut short for the next timer
+            let poll_delay = self.poll_timeout();
+            let poll_due = poll_delay.map_or(false, |d| remaining.map_or(true, |t| d <= t));
+            let t = if poll_due { poll_delay } else { remaining };
+
+            let mut fds = [::libc::pollfd { fd: -1, events: ::libc::POLLIN, revents: 0 }; 2 + WATCHES_MAX];
+            // once the terminal hung up, it is readable at once forever, so it is watched no more
+            if !self.hung_up { fds[0].fd = tty_fd }
+            fds[1].fd = signal_rx.load(Memord::Relaxed);
+            for (p, &(fd, _)) in fds[2..].iter_mut().zip(&self.watches[0..self.n_watches]) { p.fd = fd }
+            let ts = t.and_then(::time::Span::to_c_timespec);
+            match esyscall!(PPOLL, fds.as_mut_ptr(), 2 + self.n_watches,
+                            ts.as_ref().map_or(0 as *const ::libc::timespec, |p| p as *const _), 0, 0) {
+                Err(OsErr::EINTR) => continue,
+                Err(e) => return Err(e),
+                Ok(0) => {
+                    if !poll_due { return Ok(None) }
+                    if let Some(id) = self.timers.fire() { return Ok(Some(Event::Timer(id))) }
+                    if self.esc_deadline.map_or(true, |t| t > ::time::Point::now()) { continue }
+                    self.esc_deadline = None;
+                    return Ok(self.decoder.flush_esc(&mut self.inbuf))
+                },
+                Ok(_) => (),
+            }
+            if 0 != fds[0].revents {
+                match self.fill_inbuf() {
+                    // readable, yet nothing to read: the terminal hung up
+                    Ok(0) | Err(OsErr::EIO) => return Ok(self.hangup()),
+                    Err(e) => return Err(e),
+                    Ok(_) => if let Some(ev) = self.extract_event() { return Ok(ev) },
+                }
             }
-            if ::libc::FD_ISSET(winch_fds[0], &events as *const _ as *mut _) {
-                esyscall!(READ, winch_fds[0], &mut mem::MaybeUninit::<[u8; 4]>::uninit().assume_init() as *mut _, 4)?;
-                self.update_size();
-                return Ok(Some(Event::Resize(self.term_size.0 as _, self.term_size.1 as _)))
+            if 0 != fds[1].revents {
+                let mut msg = [0u64; 2];
+                esyscall!(READ, signal_rx.load(Memord::Relaxed), &mut msg as *mut u64, 16)?;
+                match msg[0] as c_int {
+                    0 => return Ok(Some(Event::User(msg[1]))),
+                    ::libc::SIGTSTP => self.suspend()?,
+                    ::libc::SIGHUP => if let Some(ev) = self.hangup() { return Ok(Some(ev)) },
+                    ::libc::SIGCONT => {
+                        self.start()?;
+                        self.present();
+                        return Ok(Some(Event::Resumed))
+                    },
+                    _ => {
+                        self.update_size();
+                        if let Some(r) = self.recorder.as_mut() { r.resize(self.term_size.0, self.term_size.1)? }
+                        return Ok(Some(Event::Resize(self.term_size.0 as _, self.term_size.1 as _)))
+                    },
+                }
+            }
+            for k in 0..self.n_watches {
+                let k = (self.watch_next + k) % self.n_watches;
+                let (_, token) = self.watches[k];
+                if 0 != fds[2 + k].revents {
+                    self.watch_next = k + 1;
+                    return Ok(Some(Event::Ready(token)))
+                }
             }
         }
     } }
 
+    /// Read input until `done` holds of the decoder or of an event, or `deadline` passes. Events
+    /// which come meanwhile are kept for later calls of `fetch_event`, except the one for which
+    /// `done` holds, which is returned.
+    ///
+    /// Fails with `EIO` once the terminal hung up, as no reply can come.
+    fn await_reply<F>(&mut self, deadline: ::time::Point, done: F) -> Result<Option<Event>, OsErr>
+      where F: Fn(&input::Decoder, Option<&Event>) -> bool {
+        loop {
+            if self.hung_up { return Err(OsErr::EIO) }
+            let now = ::time::Point::now();
+            // no more is read once the queue is full, as an event then read could not be kept
+            if done(&self.decoder, None) || now >= deadline || self.queue.is_full() { return Ok(None) }
+            if let Some(ev) = self.read_event(Some(deadline - now))? {
+                if done(&self.decoder, Some(&ev)) { return Ok(Some(ev)) }
+                self.queue.push(ev);
+            }
+        }
+    }
+
+    /// Ask the terminal where the cursor is, and wait up to `timeout` for the reply.
+    ///
+    /// Returns zero-based `(x, y)`, or `None` if the terminal did not reply in time. Events which
+    /// come first are kept for later calls of `fetch_event`. A reply which comes late, but within
+    /// another `timeout`, is returned by `fetch_event` as `Event::CursorPos`.
+    ///
+    /// Fails with `EIO` if the terminal hangs up; `Event::Hangup` is then kept for `fetch_event`.
+    pub fn query_cursor_pos(&mut self, timeout: ::time::Span) -> Result<Option<(usize, usize)>, OsErr> {
+        use core::fmt::Write;
+        let _ = self.term_writer.w.write_str("\x1B[6n");
+        self.term_writer.w.flush();
+        self.decoder.cpr_pending += 1;
+
+        let deadline = ::time::Point::now() + timeout;
+        let is_cpr = |_: &input::Decoder, ev: Option<&Event>| match ev { Some(&Event::CursorPos(..)) => true, _ => false };
+        if let Some(Event::CursorPos(x, y)) = self.await_reply(deadline, is_cpr)? { return Ok(Some((x as _, y as _))) }
+        self.cpr_deadline = Some(::time::Point::now() + timeout);
+        Ok(None)
+    }
+
+    /// Ask the terminal what it is, by DA1, DA2, and XTVERSION, and wait up to `timeout` for the
+    /// replies.
+    ///
+    /// Events which come first are kept for later calls of `fetch_event`. Nearly all terminals
+    /// reply to DA1, and they reply in order, so this returns early once that reply comes.
+    ///
+    /// Fails with `EIO` if the terminal hangs up, as `query_cursor_pos` does.
+    pub fn probe_terminal(&mut self, timeout: ::time::Span) -> Result<&TerminalInfo, OsErr> {
+        use core::fmt::Write;
+        let _ = self.term_writer.w.write_str("\x1B[>0q\x1B[>c\x1B[c");
+        self.term_writer.w.flush();
+        self.decoder.da_pending = true;
+
+        let r = self.await_reply(::time::Point::now() + timeout, |d, ev| ev.is_none() && !d.da_pending);
+        self.decoder.da_pending = false;
+        r?;
+        Ok(&self.decoder.info)
+    }
+
+    /// Return the terminfo entry of `$TERM`, from which the `UI` takes its keys and
+    /// control sequences, if one was found; else it takes them from a built-in table.
+    #[inline]
+    pub fn terminfo(&self) -> Option<TermInfo<'static>> { terminfo::load_info() }
+
+    /// Return the path of the terminfo file read, e.g. "/usr/share/terminfo/x/xterm", to
+    /// tell why keys or control sequences are wrong.
+    ///
+    /// The search is as of ncurses: `$TERMINFO`, "~/.terminfo", `$TERMINFO_DIRS`, and then
+    /// "/etc/terminfo", "/lib/terminfo", and "/usr/share/terminfo", in each under the first
+    /// letter of `$TERM` or its hexadecimal code.
+    #[inline]
+    pub fn terminfo_path(&self) -> Option<&'static Nul<u8>> { terminfo::load_path() }
+
+    /// Ask the terminal by XTGETTCAP for the control sequences of the keys and functions which
+    /// cursebox uses, wait up to `timeout` for the replies, and use what it tells in preference
+    /// to terminfo. Return the number of capabilities the terminal told. What an earlier probe
+    /// told is forgotten.
+    ///
+    /// This is worth doing where terminfo is missing or wrong. As terminals which lack XTGETTCAP
+    /// may echo the queries, nothing is sent, and 0 is returned, unless the terminal
+    /// [replied to XTVERSION](query/struct.Features.html#associatedconstant.XtVersion) when
+    /// [probed](#method.probe_terminal).
+    ///
+    /// Fails with `EIO` if the terminal hangs up, as `query_cursor_pos` does.
+    pub fn probe_termcaps(&mut self, timeout: ::time::Span) -> Result<usize, OsErr> {
+        use core::fmt::Write;
+        if !self.decoder.info.features().contains(query::Features::XtVersion) { return Ok(0) }
+        // the former replies are overwritten, so they are forgotten in favour of terminfo, even
+        // while the new ones come
+        let terminfo::Spec { keys, funcs } = self.decoder.tcap.reset();
+        self.decoder.keys.terminfo = keys;
+        self.term_writer.funcs = funcs;
+        let _ = terminfo::Merge::write_queries(&mut self.term_writer.w);
+        let _ = self.term_writer.w.write_str("\x1B[c");
+        self.term_writer.w.flush();
+        self.decoder.da_pending = true;
+
+        let r = self.await_reply(::time::Point::now() + timeout, |d, ev| ev.is_none() && !d.da_pending);
+        self.decoder.da_pending = false;
+        r?;
+        let terminfo::Spec { keys, funcs } = self.decoder.tcap.spec;
+        self.decoder.keys.terminfo = keys;
+        self.term_writer.funcs = funcs;
+        Ok(self.decoder.tcap.n)
+    }
+
+    /// Return what the terminal said of itself when last [probed](#method.probe_terminal).
+    #[inline]
+    pub fn terminal_info(&self) -> &TerminalInfo { &self.decoder.info }
+
     pub fn set_cursor(&mut self, cx: usize, cy: usize) {
         use term::is_cursor_hidden;
         if is_cursor_hidden(self.cursor_x, self.cursor_y) && !is_cursor_hidden(cx, cy) {
@@ -285,20 +755,33 @@ impl<A: Alloc> UI<A> {
         }
         self.cursor_x = cx;
         self.cursor_y = cy;
-        if !is_cursor_hidden(cx, cy) { term::write_cursor(&mut self.term_writer.w, cx, cy); }
+        if !is_cursor_hidden(cx, cy) { self.term_writer.write_cursor(cx, cy); }
     }
 
     #[inline]
     pub fn get_cursor(&self) -> (usize, usize) { (self.cursor_x, self.cursor_y) }
 
     #[inline]
-    pub fn input_mode_mut(&mut self) -> &mut input::Mode { &mut self.input_mode }
+    pub fn input_mode_mut(&mut self) -> &mut input::Mode { &mut self.decoder.mode }
+
+    /// Recognize the byte sequence `seq` in input as the given key, e.g. `Key::User(n)` for some
+    /// nonstandard key, in preference to any shorter sequence it begins with.
+    ///
+    /// Fails with `EINVAL` if `seq` is empty or longer than 16 bytes, or `ENOSPC` if too many
+    /// sequences are already registered.
+    pub fn register_key(&mut self, seq: &[u8], mod_: Mod, key: Key) -> Result<(), OsErr> {
+        if 0 == seq.len() || seq.len() > input::CUSTOM_SEQ_LEN_MAX { return Err(OsErr::EINVAL) }
+        if self.decoder.keys.register(seq, mod_, key) { Ok(()) } else { Err(OsErr::ENOSPC) }
+    }
+
+    #[inline]
+    pub fn input_flags_mut(&mut self) -> &mut input::Flags { &mut self.decoder.flags }
 
     /// Set the input mode and return the former input mode.
     #[deprecated(note = "use `input_mode_mut`")]
     #[inline]
     pub fn select_input_mode(&mut self, mode: Option<input::Mode>) -> input::Mode {
-        if let Some(mode) = mode { mem::replace(&mut self.input_mode, mode) } else { self.input_mode }
+        if let Some(mode) = mode { mem::replace(&mut self.decoder.mode, mode) } else { self.decoder.mode }
     }
 
     /// Return a `Printer` which writes to the screen with the given attributes beginning at
@@ -313,8 +796,8 @@ impl<A: Alloc> Drop for UI<A> {
     #[inline]
     fn drop(&mut self) { unsafe {
         self.stop();
-        File::new_unchecked(winch_fds[0] as _);
-        File::new_unchecked(winch_fds[1] as _);
+        if restore::is_enabled() { restore::disable() }
+        for &sig in &handled_signals { let _ = signal_default(sig); }
         lock.store(false, Memord::Release);
     } }
 }
@@ -0,0 +1,113 @@
+//! What the terminal says of itself
+//!
+//! See [`UI::probe_terminal`](../struct.UI.html#method.probe_terminal).
+
+use core::str;
+
+const VERSION_LEN_MAX: usize = 64;
+
+/// What the terminal says of itself, in reply to primary and secondary device attribute
+/// requests (DA1 and DA2) and XTVERSION
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalInfo {
+    version: [u8; VERSION_LEN_MAX],
+    version_len: usize,
+    da1: Option<u32>,
+    da2: Option<(u32, u32)>,
+    features: Features,
+}
+
+bitflags! {
+    /// Features which the terminal claims in its primary device attributes
+    ///
+    /// These are only reported; cursebox enables no protocol by them, but uses XTGETTCAP only
+    /// given `XtVersion`. It is for the caller to choose what to use, e.g. OSC 52 given
+    /// `Clipboard`.
+    pub struct Features: u32 {
+        const Columns132     = 1 << 0;
+        const Printer        = 1 << 1;
+        const Regis          = 1 << 2;
+        const Sixel          = 1 << 3;
+        const SelectiveErase = 1 << 4;
+        const UserKeys       = 1 << 5;
+        const NationalCharsets = 1 << 6;
+        const Windowing      = 1 << 7;
+        const HorizScroll    = 1 << 8;
+        const Color          = 1 << 9;
+        const RectEdit       = 1 << 10;
+        const TextLocator    = 1 << 11;
+        /// Clipboard access by OSC 52
+        const Clipboard      = 1 << 12;
+        /// The terminal replied to XTVERSION, so it likely understands other xterm extensions,
+        /// e.g. XTGETTCAP.
+        const XtVersion      = 1 << 31;
+    }
+}
+
+static da1_features: [(u32, Features); 13] = [
+    (1, Features::Columns132), (2, Features::Printer), (3, Features::Regis), (4, Features::Sixel),
+    (6, Features::SelectiveErase), (8, Features::UserKeys), (9, Features::NationalCharsets),
+    (18, Features::Windowing), (21, Features::HorizScroll), (22, Features::Color),
+    (28, Features::RectEdit), (29, Features::TextLocator), (52, Features::Clipboard),
+];
+
+/// Names of emulators which identify themselves by the terminal type in DA2
+static da2_names: [(u32, &str); 4] = [(77, "mintty"), (83, "screen"), (84, "tmux"), (85, "rxvt-unicode")];
+
+impl TerminalInfo {
+    pub const unknown: Self = Self {
+        version: [0; VERSION_LEN_MAX], version_len: 0, da1: None, da2: None, features: Features::empty(),
+    };
+
+    /// Return the name of the emulator, e.g. "XTerm", "kitty", or "tmux", per XTVERSION or
+    /// else DA2, if known.
+    pub fn name(&self) -> Option<&str> {
+        match self.xtversion() {
+            Some(s) => Some(s.split(|c| '(' == c || ' ' == c).next().unwrap_or(s)),
+            None => da2_names.iter().find(|&&(n, _)| Some(n) == self.da2.map(|(n, _)| n)).map(|&(_, s)| s),
+        }
+    }
+
+    /// Return the version of the emulator, per XTVERSION, if known.
+    pub fn version(&self) -> Option<&str> {
+        let s = self.xtversion()?;
+        let k = s.find(|c| '(' == c || ' ' == c)?;
+        Some(s[k+1..].trim_end_matches(')'))
+    }
+
+    /// Return the whole XTVERSION reply, e.g. "XTerm(367)".
+    pub fn xtversion(&self) -> Option<&str> {
+        if 0 == self.version_len { None } else { str::from_utf8(&self.version[0..self.version_len]).ok() }
+    }
+
+    /// Return the conformance level per DA1, e.g. 62 for VT220 or 64 for VT420.
+    #[inline]
+    pub fn conformance(&self) -> Option<u32> { self.da1 }
+
+    /// Return the terminal type and firmware version per DA2.
+    #[inline]
+    pub fn da2(&self) -> Option<(u32, u32)> { self.da2 }
+
+    /// Return the features claimed in DA1, and whether the terminal replied to XTVERSION.
+    #[inline]
+    pub fn features(&self) -> Features { self.features }
+
+    pub(crate) fn set_da1(&mut self, ps: &[u32]) {
+        let (&class, ps) = match ps.split_first() { Some(x) => x, None => return };
+        self.da1 = Some(class);
+        for p in ps {
+            if let Some(&(_, f)) = da1_features.iter().find(|&&(q, _)| *p == q) { self.features |= f }
+        }
+    }
+
+    pub(crate) fn set_da2(&mut self, ps: &[u32]) {
+        self.da2 = Some((ps.get(0).cloned().unwrap_or(0), ps.get(1).cloned().unwrap_or(0)));
+    }
+
+    pub(crate) fn set_xtversion(&mut self, s: &[u8]) {
+        let n = ::core::cmp::min(s.len(), VERSION_LEN_MAX);
+        self.version[0..n].copy_from_slice(&s[0..n]);
+        self.version_len = n;
+        self.features |= Features::XtVersion;
+    }
+}
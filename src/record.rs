@@ -0,0 +1,228 @@
+//! Recording of raw input, and its replay
+//!
+//! [`UI::start_recording`](../struct.UI.html#method.start_recording) records the bytes read
+//! from the terminal, and its size whenever it changes, to a file; a [`Player`](struct.Player.html)
+//! decodes such a recording into the events the `UI` would have returned, with no terminal.
+//!
+//! # Format
+//!
+//! A recording begins with the 8 bytes `b"CBREC\0\0\x01"`, the last being the format version.
+//! Each record thereafter is a head of 13 bytes:
+//!
+//! - `time: u64`, nanoseconds since the recording began
+//! - `kind: u8`
+//! - `len: u32`, the length of the payload which follows
+//!
+//! where the integers are little-endian. The kinds are:
+//!
+//! - 0: input: the payload is the bytes read.
+//! - 1: resize: the payload is the width and height, each `u16`.
+//!
+//! Records of other kinds are skipped.
+
+use core::num::NonZeroUsize;
+use io::{Read, Write};
+use loca::Alloc;
+use time::{Point, Span};
+use unix::{file::File, err::OsErr};
+
+use input::{self, Event};
+use ringbuffer::Ringbuffer;
+use terminfo;
+
+const MAGIC: [u8; 8] = *b"CBREC\0\0\x01";
+const HEAD_LEN: usize = 13;
+
+const KIND_INPUT: u8 = 0;
+const KIND_RESIZE: u8 = 1;
+
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    file: File,
+    start: Point,
+}
+
+impl Recorder {
+    pub(crate) fn new(mut file: File) -> Result<Self, OsErr> {
+        file.write_all(&MAGIC).map_err(|(e, _)| e)?;
+        Ok(Self { file, start: Point::now() })
+    }
+
+    /// Record input, read in 2 parts, as it may wrap around the buffer.
+    #[inline]
+    pub(crate) fn input(&mut self, parts: [&[u8]; 2]) -> Result<(), OsErr> { self.record(KIND_INPUT, parts) }
+
+    pub(crate) fn resize(&mut self, w: u16, h: u16) -> Result<(), OsErr> {
+        let (w, h) = (w.to_le_bytes(), h.to_le_bytes());
+        self.record(KIND_RESIZE, [&[w[0], w[1], h[0], h[1]], &[]])
+    }
+
+    fn record(&mut self, kind: u8, payload: [&[u8]; 2]) -> Result<(), OsErr> {
+        let mut head = [0; HEAD_LEN];
+        head[0..8].copy_from_slice(&(self.start.elapsed().to_ns() as u64).to_le_bytes());
+        head[8] = kind;
+        head[9..13].copy_from_slice(&((payload[0].len() + payload[1].len()) as u32).to_le_bytes());
+        self.file.write_all(&head).map_err(|(e, _)| e)?;
+        for bs in &payload { self.file.write_all(bs).map_err(|(e, _)| e)? }
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn into_file(self) -> File { self.file }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Head {
+    time: Span,
+    kind: u8,
+    len: usize,
+}
+
+/// Player of a recording, which decodes it as the `UI` would its terminal
+///
+/// Delays are taken from the recording, not waited, so replay is deterministic, e.g. whether an
+/// escape in `Mode::Delay` is a key or begins a sequence.
+#[derive(Debug)]
+pub struct Player<A: Alloc> {
+    file: File,
+    decoder: input::Decoder,
+    inbuf: Ringbuffer<A>,
+    time: Span,
+    head: Option<Head>,
+}
+
+impl<A: Alloc> Player<A> {
+    /// Play the recording `file`, decoding its input per the terminfo of `$TERM`, which ought to
+    /// be as when it was recorded.
+    ///
+    /// Fails with `EINVAL` if `file` is not a recording.
+    pub fn new_in(mut file: File, alloc: A) -> Result<Self, OsErr> {
+        let mut magic = [0; 8];
+        file.read_full::<OsErr>(&mut magic).map_err(|(e, _)| e)?;
+        if MAGIC != magic { return Err(OsErr::EINVAL) }
+        let terminfo::Spec { keys, .. } = terminfo::load().unwrap_or(terminfo::Spec::empty);
+        Ok(Self {
+            file,
+            decoder: input::Decoder::new(input::KeyTable::new(keys), terminfo::Merge::new(terminfo::Spec::empty, &mut [])),
+            inbuf: Ringbuffer::new_in(alloc),
+            time: Span::from_ns(0),
+            head: None,
+        })
+    }
+
+    /// The input mode, which ought to be as the `UI`'s when it was recorded
+    #[inline]
+    pub fn input_mode_mut(&mut self) -> &mut input::Mode { &mut self.decoder.mode }
+
+    #[inline]
+    pub fn input_flags_mut(&mut self) -> &mut input::Flags { &mut self.decoder.flags }
+
+    /// Return the time of the last record played, since the recording began.
+    #[inline]
+    pub fn time(&self) -> Span { self.time }
+
+    /// Return the next event of the recording, or `None` at its end.
+    pub fn next_event(&mut self) -> Result<Option<Event>, OsErr> {
+        loop {
+            if let Some(ev) = self.decoder.extract_event(&mut self.inbuf) { return Ok(Some(ev)) }
+
+            let head = match self.head.take() {
+                Some(head) => head,
+                None => match self.read_head()? {
+                    Some(head) => head,
+                    None => return Ok(self.decoder.flush_esc(&mut self.inbuf)),
+                },
+            };
+
+            // the escape is held no longer than the user paused after it
+            if let input::Mode::Delay(d) = self.decoder.mode {
+                if input::is_esc_pending(&self.inbuf) && head.time - self.time >= d {
+                    self.head = Some(head);
+                    return Ok(self.decoder.flush_esc(&mut self.inbuf))
+                }
+            }
+            self.time = head.time;
+
+            let mut buf = [0; 0x100];
+            match head.kind {
+                KIND_RESIZE if 4 == head.len => {
+                    self.file.read_full::<OsErr>(&mut buf[0..4]).map_err(|(e, _)| e)?;
+                    let w = u16::from_le_bytes([buf[0], buf[1]]);
+                    let h = u16::from_le_bytes([buf[2], buf[3]]);
+                    return Ok(Some(Event::Resize(w as _, h as _)))
+                },
+                kind => {
+                    let mut len = head.len;
+                    while let Some(n) = NonZeroUsize::new(::core::cmp::min(len, buf.len())) {
+                        let buf = &mut buf[0..n.get()];
+                        self.file.read_full::<OsErr>(buf).map_err(|(e, _)| e)?;
+                        if KIND_INPUT == kind && !self.inbuf.push(buf) { return Err(OsErr::ENOMEM) }
+                        len -= n.get();
+                    }
+                },
+            }
+        }
+    }
+
+    /// Read the head of the next record, or `None` at the end of the recording.
+    fn read_head(&mut self) -> Result<Option<Head>, OsErr> {
+        let mut head = [0; HEAD_LEN];
+        match self.file.try_read_full(&mut head).map_err(|(e, _)| e)? {
+            HEAD_LEN => (),
+            _ => return Ok(None),
+        }
+        let mut time = [0; 8];
+        time.copy_from_slice(&head[0..8]);
+        Ok(Some(Head {
+            time: Span::from_ns(u64::from_le_bytes(time) as _),
+            kind: head[8],
+            len: u32::from_le_bytes([head[9], head[10], head[11], head[12]]) as _,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use default_allocator::Heap;
+    use input::{Key, Mod, Mode};
+    use self::std::vec::Vec;
+    use unix::file::{new_pipe, OpenFlags};
+    use super::*;
+
+    fn key(mod_: Mod, c: char) -> Event { Event::Key(mod_, Key::Char(c)) }
+
+    #[test]
+    fn round_trip() {
+        let (rx, tx) = new_pipe(OpenFlags::empty()).unwrap();
+        let mut rec = Recorder::new(tx).unwrap();
+        rec.input([b"a\x1B", b"b"]).unwrap();
+        rec.resize(80, 24).unwrap();
+        rec.input([b"\x1B", &[]]).unwrap();
+        // the user paused 2 s after the escape
+        rec.start -= Span::from_ns(2_000_000_000);
+        rec.input([b"x", &[]]).unwrap();
+        rec.input([b"\x1B", &[]]).unwrap();
+        rec.input([b"y", &[]]).unwrap();
+        rec.input([b"\x1B", &[]]).unwrap();
+        drop(rec.into_file());
+
+        let mut player = Player::new_in(rx, Heap).unwrap();
+        *player.input_mode_mut() = Mode::Delay(Span::from_ns(1_000_000_000));
+        let mut evs = Vec::new();
+        while let Some(ev) = player.next_event().unwrap() { evs.push(ev) }
+        assert_eq!(&[key(Mod::empty(), 'a'), key(Mod::Alt, 'b'), Event::Resize(80, 24),
+                     Event::Key(Mod::empty(), Key::Esc), key(Mod::empty(), 'x'), key(Mod::Alt, 'y'),
+                     Event::Key(Mod::empty(), Key::Esc)][..], &evs[..]);
+        assert!(player.time() >= Span::from_ns(2_000_000_000));
+    }
+
+    #[test]
+    fn not_recording() {
+        let (rx, mut tx) = new_pipe(OpenFlags::empty()).unwrap();
+        tx.write_all(b"CBREC\0\0\x02").map_err(|(e, _)| e).unwrap();
+        drop(tx);
+        assert_eq!(OsErr::EINVAL, Player::new_in(rx, Heap).map(|_| ()).unwrap_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Restoration of the terminal if the program dies without dropping the `UI`
+//!
+//! See [`UI::restore_on_fatal`](../struct.UI.html#method.restore_on_fatal).
+
+use core::{mem, sync::atomic::{AtomicBool, Ordering as Memord}};
+use libc::c_int;
+use unix::{file::File, err::OsErr};
+use unix_tty::TtyExt;
+
+pub(crate) const SEQ_LEN_MAX: usize = 256;
+
+/// What to restore: the tty, its original modes, and the sequences to leave the UI
+struct State {
+    fd: c_int,
+    tios: ::libc::termios,
+    seq: [u8; SEQ_LEN_MAX],
+    seq_len: usize,
+}
+
+static mut state: Option<State> = None;
+
+/// Whether `state` is valid and the terminal is to be restored, i.e. the UI is started
+static armed: AtomicBool = AtomicBool::new(false);
+static enabled: AtomicBool = AtomicBool::new(false);
+
+/// SIGHUP is among them, so while this is enabled, a hangup kills the program at once rather
+/// than being reported as `Event::Hangup`.
+static fatal_signals: [c_int; 7] = [
+    ::libc::SIGTERM, ::libc::SIGINT, ::libc::SIGQUIT, ::libc::SIGABRT, ::libc::SIGSEGV, ::libc::SIGBUS,
+    ::libc::SIGHUP,
+];
+
+#[inline]
+pub(crate) fn is_enabled() -> bool { enabled.load(Memord::Relaxed) }
+
+/// Handle fatal signals, and panics if the "std" feature is on, by restoring the terminal.
+pub(crate) unsafe fn enable() -> Result<(), OsErr> {
+    for &sig in &fatal_signals {
+        ::unix_signal::sigaction(sig as _, fatal_handler, ::unix_signal::Flags::empty())?;
+    }
+    #[cfg(feature = "std")]
+    set_panic_hook();
+    enabled.store(true, Memord::Relaxed);
+    Ok(())
+}
+
+/// Handle fatal signals by default again.
+pub(crate) unsafe fn disable() {
+    disarm();
+    enabled.store(false, Memord::Relaxed);
+    for &sig in &fatal_signals { let _ = ::signal_default(sig); }
+}
+
+/// Take note of what to restore, as the UI is started.
+pub(crate) fn arm(fd: c_int, tios: ::libc::termios, seq: &[u8]) {
+    disarm();
+    let mut s = State { fd, tios, seq: [0; SEQ_LEN_MAX], seq_len: 0 };
+    s.seq_len = ::core::cmp::min(seq.len(), SEQ_LEN_MAX);
+    s.seq[0..s.seq_len].copy_from_slice(&seq[0..s.seq_len]);
+    unsafe { state = Some(s) };
+    armed.store(true, Memord::Release);
+}
+
+/// Take note that the UI is stopped, so there is nothing to restore.
+#[inline]
+pub(crate) fn disarm() { armed.store(false, Memord::Release) }
+
+/// Restore the terminal, if it is armed, at most once. This is async-signal-safe.
+pub(crate) unsafe fn restore() {
+    if !armed.swap(false, Memord::Acquire) { return }
+    let s = match state { Some(ref s) => s, None => return };
+    syscall!(WRITE, s.fd, s.seq.as_ptr(), s.seq_len);
+    let mut tty = File::new_unchecked(s.fd as _);
+    let _ = tty.set_termios(s.tios, ::unix_tty::termios::When::Now);
+    mem::forget(tty);
+}
+
+unsafe extern "C" fn fatal_handler(sig: usize, _: &::libc::siginfo_t, _: &::libc::ucontext_t) {
+    restore();
+    // the signal is blocked while it is handled, so it is delivered once this returns
+    let _ = ::signal_default(sig as _);
+    syscall!(KILL, syscall!(GETPID), sig);
+}
+
+#[cfg(feature = "std")]
+fn set_panic_hook() {
+    static hook_set: AtomicBool = AtomicBool::new(false);
+    if hook_set.swap(true, Memord::Relaxed) { return }
+    let hook = ::std::panic::take_hook();
+    ::std::panic::set_hook(::std::boxed::Box::new(move |info| {
+        unsafe { restore() };
+        hook(info)
+    }));
+}
@@ -1,106 +1,189 @@
-use util::*;
-
-#[derive(Debug)]
-pub struct Ringbuffer<'a> {
-    pub(crate) buf: &'a mut [u8],
-    pub(crate) begin: *mut u8,
-    pub(crate) end: *mut u8,
+//! Byte queue in a ring, which grows as needed
+
+use containers::collections::Vec;
+use core::{cmp, fmt, iter};
+use loca::Alloc;
+use unix::{err::OsErr, file::File};
+
+/// Byte queue in a ring, which grows as needed, so never drops data
+pub struct Ringbuffer<A: Alloc> {
+    /// Storage, whose length is the capacity
+    buf: Vec<u8, A>,
+    begin: usize,
+    len: usize,
 }
 
-impl<'a> Ringbuffer<'a> {
-    pub fn free_space(&self) -> usize {
-        if self.begin.is_null() && self.end.is_null() { self.buf.len() }
-        else if self.end > self.begin { self.buf.len() - ptr_diff(self.end, self.begin) - 1 }
-        else { ptr_diff(self.begin, self.end) - 1 }
+impl<A: Alloc> fmt::Debug for Ringbuffer<A> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (xs, ys) = self.as_slices();
+        f.debug_list().entries(xs.iter().chain(ys)).finish()
     }
+}
+
+impl<A: Alloc> Ringbuffer<A> {
+    #[inline]
+    pub const fn new_in(alloc: A) -> Self { Self { buf: Vec::new_in(alloc), begin: 0, len: 0 } }
 
-    pub fn data_size(&self) -> usize {
-        if self.begin.is_null() && self.end.is_null() { 0 }
-        else if self.end >= self.begin { ptr_diff(self.end, self.begin) + 1 }
-        else { self.buf.len() - ptr_diff(self.begin, self.end) + 1 }
+    /// Make a `Ringbuffer` which holds `cap` bytes before it must grow, or `None` if out of
+    /// memory.
+    #[inline]
+    pub fn with_capacity_in(alloc: A, cap: usize) -> Option<Self> {
+        let mut rb = Self::new_in(alloc);
+        if rb.reserve(cap) { Some(rb) } else { None }
     }
 
-    #[inline(always)]
-    pub fn clear(&mut self) { self.begin = 0 as _; self.end = 0 as _; }
+    /// Number of bytes held
+    #[inline]
+    pub fn len(&self) -> usize { self.len }
 
-    #[inline(always)]
-    fn buf_end_ptr(&self) -> *mut u8 { self.buf.as_ptr().wrapping_add(self.buf.len()) as _ }
+    #[inline]
+    pub fn is_empty(&self) -> bool { 0 == self.len }
 
-    #[inline(always)]
-    fn to_end_from(&self, ptr: *mut u8) -> usize { ptr_diff(self.buf_end_ptr(), ptr) }
+    /// Number of bytes which can be held before it must grow
+    #[inline]
+    pub fn capacity(&self) -> usize { self.buf.len() }
 
-    pub fn push(&mut self, bs: &[u8]) { unsafe {
-        if self.free_space() < bs.len() { return }
-        if self.begin.is_null() && self.end.is_null() {
-            copy_from_slice(self.buf.as_mut_ptr(), bs);
-            self.begin = self.buf.as_mut_ptr();
-            self.end   = self.begin.add((bs.len() - 1) as _);
-            return
-        }
+    #[inline]
+    pub fn free_space(&self) -> usize { self.capacity() - self.len }
 
-        self.end = self.end.add(1);
-        if self.begin < self.end && self.to_end_from(self.begin) < bs.len() {
-            // make a cut
-            let (xs, ys) = bs.split_at_unchecked(self.to_end_from(self.end));
-            copy_from_slice(self.end, xs);
-            copy_from_slice(self.buf.as_mut_ptr(), ys);
-            self.end = self.buf.as_mut_ptr().add(ys.len() - 1);
-        } else {
-            // fits with no cut
-            copy_from_slice(self.end, bs);
-            self.end = self.end.add(bs.len() - 1);
-        }
-    } }
+    #[inline]
+    pub fn clear(&mut self) { self.begin = 0; self.len = 0; }
 
-    pub unsafe fn pop_raw(&mut self, ptr: *mut u8, mut size: usize) {
-        if self.data_size() < size { return }
-        let need_clear = self.data_size() == size;
+    /// Return the bytes held, in order, in 2 parts, as they may wrap around.
+    #[inline]
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.begin + self.len;
+        if end <= self.capacity() { (&self.buf[self.begin..end], &[]) }
+        else { (&self.buf[self.begin..], &self.buf[0..end - self.capacity()]) }
+    }
 
-        if self.begin < self.end || self.to_end_from(self.begin) >= size {
-            if !ptr.is_null() { ptr.copy_from_nonoverlapping(self.begin, size) }
-            self.begin = self.begin.add(size);
-        } else {
-            let s = self.to_end_from(self.begin);
-            if !ptr.is_null() { ptr.copy_from_nonoverlapping(self.begin, s) }
-            size -= s;
-            if !ptr.is_null() { ptr.add(s).copy_from_nonoverlapping(self.buf.as_ptr(), size) }
-            self.begin = self.buf.as_mut_ptr().add(size);
+    /// Make room for at least `n` more bytes. Returns `false` if out of memory.
+    pub fn reserve(&mut self, n: usize) -> bool {
+        if self.free_space() >= n { return true }
+        let cap = self.capacity();
+        let new_cap = match cmp::max(self.len + n, cap << 1).checked_next_power_of_two() {
+            Some(new_cap) => new_cap,
+            None => return false,
+        };
+        if !self.buf.reserve(new_cap - cap) || self.buf.extend(iter::repeat(0).take(new_cap - cap)).is_err() {
+            return false
         }
+        // what wrapped around now goes after the rest, as there is room
+        let wrapped = (self.begin + self.len).saturating_sub(cap);
+        let (xs, ys) = self.buf.split_at_mut(cap);
+        ys[0..wrapped].copy_from_slice(&xs[0..wrapped]);
+        true
+    }
 
-        if need_clear { self.clear() }
+    /// Append `bs`. Returns `false` if out of memory.
+    pub fn push(&mut self, bs: &[u8]) -> bool {
+        if !self.reserve(bs.len()) { return false }
+        let (xs, ys) = self.free_slices();
+        let n = cmp::min(xs.len(), bs.len());
+        xs[0..n].copy_from_slice(&bs[0..n]);
+        ys[0..bs.len() - n].copy_from_slice(&bs[n..]);
+        self.len += bs.len();
+        true
     }
 
-    #[inline]
-    pub fn skip(&mut self, n: usize) { unsafe { self.pop_raw(0 as _, n) } }
+    /// Copy the first bytes held into `bs`, as many as fit, and return how many.
+    pub fn read(&self, bs: &mut [u8]) -> usize {
+        let (xs, ys) = self.as_slices();
+        let m = cmp::min(xs.len(), bs.len());
+        bs[0..m].copy_from_slice(&xs[0..m]);
+        let n = cmp::min(ys.len(), bs.len() - m);
+        bs[m..m+n].copy_from_slice(&ys[0..n]);
+        m + n
+    }
 
-    #[inline]
-    pub fn pop(&mut self, bs: &mut [u8]) { unsafe { self.pop_raw(bs.as_mut_ptr(), bs.len()) } }
+    /// Drop the first `n` bytes held, or all if fewer.
+    pub fn skip(&mut self, n: usize) {
+        let n = cmp::min(n, self.len);
+        self.len -= n;
+        self.begin = if 0 == self.len { 0 } else { (self.begin + n) % self.capacity() };
+    }
 
+    /// Take the first bytes held into `bs`, as many as fit, and return how many.
     #[inline]
-    pub fn read(&self, bs: &mut [u8]) { unsafe {
-        let mut other = ::core::ptr::read(self);
-        other.pop_raw(bs.as_mut_ptr(), bs.len());
-        ::core::mem::forget(other);
-    } }
-
-    pub fn push_from_file(&mut self, file: &mut ::unix::file::File) -> Result<usize, ::unix::err::OsErr> { unsafe {
-        use core::slice;
+    pub fn pop(&mut self, bs: &mut [u8]) -> usize {
+        let n = self.read(bs);
+        self.skip(n);
+        n
+    }
+
+    /// Read from `file` as much as it gives at once, growing first if full, and return how many
+    /// bytes were read.
+    pub fn push_from_file(&mut self, file: &mut File) -> Result<usize, OsErr> {
         use io::Read;
 
-        if self.begin.is_null() && self.end.is_null() {
-            let n = file.read(self.buf)?;
-            self.begin = self.buf.as_mut_ptr();
-            self.end   = self.begin.add((n - 1) as _);
-            return Ok(n)
+        if !self.reserve(1) { return Err(OsErr::ENOMEM) }
+        let n = {
+            let (xs, ys) = self.free_slices();
+            file.readv(&mut [xs, ys])?
+        };
+        self.len += n;
+        Ok(n)
+    }
+
+    /// Return the free space, in order, in 2 parts, as it may wrap around.
+    fn free_slices(&mut self) -> (&mut [u8], &mut [u8]) {
+        if 0 == self.len { self.begin = 0 }
+        let (cap, begin) = (self.capacity(), self.begin);
+        let end = begin + self.len;
+        if end < cap {
+            let (xs, ys) = self.buf.split_at_mut(end);
+            (ys, &mut xs[0..begin])
+        } else {
+            (&mut self.buf[end - cap..begin], &mut [])
         }
+    }
+}
 
-        let end = self.end.add(1);
-        let n = file.readv(&mut [slice::from_raw_parts_mut(end, self.to_end_from(end)),
-                                 slice::from_raw_parts_mut(self.buf.as_mut_ptr(),
-                                                           ptr_diff(self.begin,
-                                                                    self.buf.as_mut_ptr()))])?;
-        self.end = self.end.add(n);
-        if self.end >= self.buf_end_ptr() { self.end = self.end.sub(self.buf.len()) }
-        Ok(n)
-    } }
+#[cfg(test)]
+mod tests {
+    use default_allocator::Heap;
+    use super::*;
+
+    fn contents(rb: &Ringbuffer<Heap>) -> ([u8; 16], usize) {
+        let mut bs = [0; 16];
+        let n = rb.read(&mut bs);
+        (bs, n)
+    }
+
+    #[test]
+    fn push_pop() {
+        let mut rb = Ringbuffer::with_capacity_in(Heap, 4).unwrap();
+        assert_eq!(4, rb.capacity());
+        assert!(rb.push(b"abc"));
+        let mut bs = [0; 2];
+        assert_eq!(2, rb.pop(&mut bs));
+        assert_eq!(b"ab", &bs);
+        assert_eq!(1, rb.len());
+        rb.skip(5);
+        assert!(rb.is_empty());
+    }
+
+    #[test]
+    fn reserve_wrapped() {
+        let mut rb = Ringbuffer::with_capacity_in(Heap, 4).unwrap();
+        assert!(rb.push(b"abc"));
+        rb.skip(2);
+        // wraps around
+        assert!(rb.push(b"de"));
+        assert_eq!((&b"cd"[..], &b"e"[..]), rb.as_slices());
+        assert_eq!(4, rb.capacity());
+
+        assert!(rb.push(b"fgh"));
+        assert_eq!(8, rb.capacity());
+        let (bs, n) = contents(&rb);
+        assert_eq!(b"cdefgh", &bs[0..n]);
+
+        // wraps around again
+        rb.skip(5);
+        assert!(rb.push(b"ijkl"));
+        assert_eq!(8, rb.capacity());
+        let (bs, n) = contents(&rb);
+        assert_eq!(b"hijkl", &bs[0..n]);
+    }
 }
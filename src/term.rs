@@ -2,10 +2,7 @@ use core::fmt;
 use nul::NulStr;
 
 use Attr;
-
-pub fn write_cursor<W: fmt::Write>(mut w: W, x: usize, y: usize) -> fmt::Result {
-    write!(w, "\x1B[{};{}H", y+1, x+1)
-}
+use tparm::{self, tparm, tputs, Padding, Param};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -13,6 +10,7 @@ pub struct TermWriter<W> {
     pub(crate) funcs: [&'static NulStr; T_FUNCS_NUM],
     last_pos: (usize, usize),
     last_attr: (Attr, Attr),
+    pub(crate) padding: Padding,
     pub(crate) w: W,
 }
 
@@ -21,33 +19,59 @@ impl<W: fmt::Write> TermWriter<W> {
     pub const fn new(w: W) -> Self { Self { last_pos: (!0, !0),
                                             last_attr: (Attr { bits: !0 },
                                                         Attr { bits: !0 }),
-                                     funcs: [str0_utf8!(""); T_FUNCS_NUM], w } }
+                                     funcs: [str0_utf8!(""); T_FUNCS_NUM],
+                                     padding: Padding::none, w } }
 
     #[inline]
     pub fn write_func(&mut self, func: Func) -> fmt::Result {
-        self.w.write_str(&self.funcs[func as usize][..])
+        tputs(&mut self.w, &self.funcs[func as usize][..], &self.padding)
+    }
+
+    /// Write the func at index `func` with `params` substituted, and padded.
+    fn write_func_with(&mut self, func: usize, params: &[Param]) -> fmt::Result {
+        let mut bs = [0; 0x100];
+        let mut buf = tparm::Buf::new(&mut bs);
+        tparm(&mut buf, &self.funcs[func][..], params)?;
+        tputs(&mut self.w, buf.as_str(), &self.padding)
     }
 
     pub fn write_char(&mut self, x: u32, xpos: usize, ypos: usize) -> fmt::Result {
-        if (xpos-1, ypos) != self.last_pos { write_cursor(&mut self.w, xpos, ypos)? }
+        if (xpos-1, ypos) != self.last_pos { self.write_cursor(xpos, ypos)? }
         self.last_pos = (xpos, ypos);
         write!(&mut self.w, "{}",
                match ::core::char::from_u32(x).unwrap_or('\0') { '\0' => ' ', x => x })
     }
 
+    /// Move the cursor to `(x, y)`, by `cup`, or else as ANSI.
+    pub fn write_cursor(&mut self, x: usize, y: usize) -> fmt::Result {
+        if "" == &self.funcs[Cursor as usize][..] { write!(&mut self.w, "\x1B[{};{}H", y+1, x+1) }
+        else { self.write_func_with(Cursor as usize, &[Param::Int(y as _), Param::Int(x as _)]) }
+    }
+
+    /// Set the scrolling region to rows `top` to `bottom` inclusive, by `csr`, if the terminal
+    /// has it. This may move the cursor.
+    pub fn write_scroll_region(&mut self, top: usize, bottom: usize) -> fmt::Result {
+        if "" == &self.funcs[ScrollRegion as usize][..] { return Ok(()) }
+        self.invalidate_pos();
+        self.write_func_with(ScrollRegion as usize, &[Param::Int(top as _), Param::Int(bottom as _)])
+    }
+
     pub fn write_attr(&mut self, fg: Attr, bg: Attr) -> fmt::Result {
         if (fg, bg) == self.last_attr { return Ok(()) }
-        let e = str0_utf8!("");
-        fn f(a: Attr) -> u16 {
+        self.write_func(Sgr0)?;
+        // `sgr0` leaves the colors default, so only others need be set, but as ANSI, which lacks
+        // `setaf` and `setab`, the default is set too.
+        for &(a, func, ansi) in &[(fg, SetFg as usize, 3), (bg, SetBg as usize, 4)] {
             let a = a & Attr::Default;
-            if Attr::Default == a { 9 } else { a.bits }
+            if "" == &self.funcs[func][..] {
+                write!(&mut self.w, "\x1B[{}{}m", ansi, if Attr::Default == a { 9 } else { a.bits })?
+            } else if Attr::Default != a {
+                self.write_func_with(func, &[Param::Int(a.bits as _)])?
+            }
         }
-        write!(&mut self.w, "{}\x1B[3{};4{}m{}{}{}",
-               self.funcs[Sgr0 as usize], f(fg), f(bg),
-               if fg.contains(Attr::Bold)      { self.funcs[Bold      as usize] } else { e },
-               if bg.contains(Attr::Bold)      { self.funcs[Blink     as usize] } else { e },
-               if fg.contains(Attr::Underline) { self.funcs[Underline as usize] } else { e },
-              )?;
+        if fg.contains(Attr::Bold)      { self.write_func(Bold)? }
+        if bg.contains(Attr::Bold)      { self.write_func(Blink)? }
+        if fg.contains(Attr::Underline) { self.write_func(Underline)? }
         self.last_attr = (fg, bg);
         Ok(())
     }
@@ -55,7 +79,7 @@ impl<W: fmt::Write> TermWriter<W> {
     pub fn write_clear(&mut self, cx: usize, cy: usize, fg: Attr, bg: Attr) -> fmt::Result {
         self.write_attr(fg, bg)?;
         self.write_func(ClearScreen)?;
-        if !is_cursor_hidden(cx, cy) { write_cursor(&mut self.w, cx, cy)?; }
+        if !is_cursor_hidden(cx, cy) { self.write_cursor(cx, cy)?; }
         //self.w.flush();
         self.invalidate_pos();
         Ok(())
@@ -81,9 +105,13 @@ pub enum Func {
     Reverse,
     EnterKeypad,
     ExitKeypad,
+    Cursor,
+    SetFg,
+    SetBg,
+    ScrollRegion,
 }
 
-pub const T_FUNCS_NUM: usize = 12;
+pub const T_FUNCS_NUM: usize = 16;
 
 #[inline]
 pub fn is_cursor_hidden(cx: usize, cy: usize) -> bool { (cx, cy) == (!0, !0) }
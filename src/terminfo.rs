@@ -1,5 +1,5 @@
 use chain::chain;
-use core::slice;
+use core::{fmt, str, sync::atomic::{AtomicUsize, Ordering as Memord}};
 use io::Read;
 use nul::{Nul, NulStr};
 use subslice::SubsliceExt;
@@ -7,7 +7,7 @@ use util::SliceExt;
 
 use term::T_FUNCS_NUM;
 
-const TB_KEYS_NUM: usize = 22;
+const TB_KEYS_NUM: usize = 84;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Spec<'a> {
@@ -16,41 +16,59 @@ pub struct Spec<'a> {
 }
 
 mod spec {
-    use super::Spec;
+    use nul::NulStr;
+    use super::{Spec, TB_KEYS_NUM};
 
     macro_rules! s {
         [$($x:expr),*] => ([$(str0_utf8!($x)),*]);
         [$($x:expr,)*] => ([$(str0_utf8!($x)),*]);
     }
 
+    /// Make the key table of the 22 basic keys and the 11 at the end, namely back-tab, keypad,
+    /// begin, shifted home and end, and backspace; F13 to F63 are left undefined.
+    const fn keys(base: [&'static NulStr; 22], ext: [&'static NulStr; 11]) -> [&'static NulStr; TB_KEYS_NUM] {
+        let mut keys = [str0_utf8!(""); TB_KEYS_NUM];
+        let mut i = 0;
+        while i < base.len() { keys[i] = base[i]; i += 1; }
+        let mut i = 0;
+        while i < ext.len() { keys[TB_KEYS_NUM - ext.len() + i] = ext[i]; i += 1; }
+        keys
+    }
+
     pub const rxvt_256color: Spec = Spec {
-        keys: s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
-        funcs: s!["\x1B7\x1B[?47h", "\x1B[2J\x1B[?47l\x1B8", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B=", "\x1B>",],
+        keys: keys(s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
+                   s!["\x1B[Z","\x1BOw","\x1BOy","\x1BOu","\x1BOq","\x1BOs","\x1BOM","","\x1B[7$","\x1B[8$","\x7F"]),
+        funcs: s!["\x1B7\x1B[?47h", "\x1B[2J\x1B[?47l\x1B8", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B=", "\x1B>", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 
     pub const eterm: Spec = Spec {
-        keys: s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
-        funcs: s!["\x1B7\x1B[?47h", "\x1B[2J\x1B[?47l\x1B8", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "", "",],
+        keys: keys(s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
+                   s!["","\x1B[7~","\x1B[5~","\x1BOu","\x1B[8~","\x1B[6~","\x1BOM","\x1BOu","\x1B[7$","\x1B[8$","\x7F"]),
+        funcs: s!["\x1B7\x1B[?47h", "\x1B[2J\x1B[?47l\x1B8", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "", "", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 
     pub const screen: Spec = Spec {
-        keys: s!["\x1BOP","\x1BOQ","\x1BOR","\x1BOS","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[1~","\x1B[4~","\x1B[5~","\x1B[6~","\x1BOA","\x1BOB","\x1BOD","\x1BOC"],
-        funcs: s!["\x1B[?1049h", "\x1B[?1049l", "\x1B[34h\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B[?1h\x1B=", "\x1B[?1l\x1B>",],
+        keys: keys(s!["\x1BOP","\x1BOQ","\x1BOR","\x1BOS","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[1~","\x1B[4~","\x1B[5~","\x1B[6~","\x1BOA","\x1BOB","\x1BOD","\x1BOC"],
+                   s!["\x1B[Z","","","","","","","","","","\x7F"]),
+        funcs: s!["\x1B[?1049h", "\x1B[?1049l", "\x1B[34h\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[J", "\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B[?1h\x1B=", "\x1B[?1l\x1B>", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 
     pub const rxvt_unicode: Spec = Spec {
-        keys: s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
-        funcs: s!["\x1B[?1049h", "\x1B[r\x1B[?1049l", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m\x1B(B", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B=", "\x1B>",],
+        keys: keys(s!["\x1B[11~","\x1B[12~","\x1B[13~","\x1B[14~","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[7~","\x1B[8~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
+                   s!["\x1B[Z","\x1BOw","\x1BOy","\x1BOu","\x1BOq","\x1BOs","\x1BOM","","\x1B[7$","\x1B[8$","\x7F"]),
+        funcs: s!["\x1B[?1049h", "\x1B[r\x1B[?1049l", "\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B[m\x1B(B", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B=", "\x1B>", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 
     pub const linux: Spec = Spec {
-        keys: s!["\x1B[[A","\x1B[[B","\x1B[[C","\x1B[[D","\x1B[[E","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[1~","\x1B[4~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
-        funcs: s!["", "", "\x1B[?25h\x1B[?0c", "\x1B[?25l\x1B[?1c", "\x1B[H\x1B[J", "\x1B[0;10m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "", "",],
+        keys: keys(s!["\x1B[[A","\x1B[[B","\x1B[[C","\x1B[[D","\x1B[[E","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1B[1~","\x1B[4~","\x1B[5~","\x1B[6~","\x1B[A","\x1B[B","\x1B[D","\x1B[C"],
+                   s!["\x1B\t","","","\x1B[G","","","","","","","\x7F"]),
+        funcs: s!["", "", "\x1B[?25h\x1B[?0c", "\x1B[?25l\x1B[?1c", "\x1B[H\x1B[J", "\x1B[0;10m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "", "", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 
     pub const xterm: Spec = Spec {
-        keys: s!["\x1BOP","\x1BOQ","\x1BOR","\x1BOS","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1BOH","\x1BOF","\x1B[5~","\x1B[6~","\x1BOA","\x1BOB","\x1BOD","\x1BOC"],
-        funcs: s!["\x1B[?1049h", "\x1B[?1049l", "\x1B[?12l\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B(B\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B[?1h\x1B=", "\x1B[?1l\x1B>",],
+        keys: keys(s!["\x1BOP","\x1BOQ","\x1BOR","\x1BOS","\x1B[15~","\x1B[17~","\x1B[18~","\x1B[19~","\x1B[20~","\x1B[21~","\x1B[23~","\x1B[24~","\x1B[2~","\x1B[3~","\x1BOH","\x1BOF","\x1B[5~","\x1B[6~","\x1BOA","\x1BOB","\x1BOD","\x1BOC"],
+                   s!["\x1B[Z","\x1BOw","\x1BOy","\x1BOu","\x1BOq","\x1BOs","\x1BOM","\x1BOE","\x1B[1;2H","\x1B[1;2F","\x7F"]),
+        funcs: s!["\x1B[?1049h", "\x1B[?1049l", "\x1B[?12l\x1B[?25h", "\x1B[?25l", "\x1B[H\x1B[2J", "\x1B(B\x1B[m", "\x1B[4m", "\x1B[1m", "\x1B[5m", "\x1B[7m", "\x1B[?1h\x1B=", "\x1B[?1l\x1B>", "\x1B[%i%p1%d;%p2%dH", "\x1B[3%p1%dm", "\x1B[4%p1%dm", "\x1B[%i%p1%d;%p2%dr",],
     };
 }
 
@@ -77,69 +95,330 @@ impl<'a> Spec<'a> {
     pub const empty: Self = Self { keys: [str0_utf8!(""); TB_KEYS_NUM], funcs: [str0_utf8!(""); T_FUNCS_NUM] };
 }
 
-pub fn init(buf: &mut [u8]) -> Option<Spec> { unsafe {
-    if buf.len() < TI_HEADER_LENGTH << 1 { return None }
+/// The terminfo entry of `$TERM`, what cursebox takes from it, and the path of the file it was
+/// read from
+pub struct Loaded<'a> {
+    pub info: Option<TermInfo<'a>>,
+    pub spec: Option<Spec<'a>>,
+    pub path: Option<&'a Nul<u8>>,
+}
+
+/// Load the terminfo of `$TERM` into static storage, once per process, so a `UI` and a `Player`
+/// can share it.
+fn loaded() -> &'static Loaded<'static> { unsafe {
+    // 0: not loaded; 1: loading; 2: loaded
+    static state: AtomicUsize = AtomicUsize::new(0);
+    static mut buf: [u8; 0x4000] = [0; 0x4000];
+    static mut path: [u8; 0x1000] = [0; 0x1000];
+    static mut loaded: Loaded<'static> = Loaded { info: None, spec: None, path: None };
+    loop {
+        match state.compare_exchange(0, 1, Memord::Acquire, Memord::Acquire) {
+            Ok(_) => {
+                loaded = init(&mut buf, &mut path);
+                state.store(2, Memord::Release);
+                return &loaded
+            },
+            Err(2) => return &loaded,
+            Err(_) => ::core::hint::spin_loop(),
+        }
+    }
+} }
+
+#[inline]
+pub fn load() -> Option<Spec<'static>> { loaded().spec }
 
-    if let None = load_terminfo(buf) { return init_builtin() }
+/// Return the terminfo entry of `$TERM`, if one was found.
+#[inline]
+pub fn load_info() -> Option<TermInfo<'static>> { loaded().info }
 
-    let hdr = slice::from_raw_parts(buf.as_mut_ptr() as *mut [u8; 2], TI_HEADER_LENGTH);
-    let buf = buf.get(((TI_HEADER_LENGTH + u16_le(hdr[3]) as usize) << 1) +
-                      (u16_le(hdr[1]) + u16_le(hdr[2]) + 1) as usize & !1 ..)?;
-    let hdr_4 = u16_le(hdr[4]) as usize;
-    let (str, tab) = buf.try_split_at(hdr_4 << 1)?;
-    let str = slice::from_raw_parts(str.as_ptr() as *mut [u8; 2], hdr_4);
-    let tab = &tab[0..tab.iter().rposition(|&b| 0 == b)?];
+/// Return the path of the terminfo entry of `$TERM`, if one was found.
+#[inline]
+pub fn load_path() -> Option<&'static Nul<u8>> { loaded().path }
+
+/// Read the terminfo entry of `$TERM` into `buf`, and take from it the keys and funcs which
+/// cursebox uses; failing that, take them from the built-in table. `path` is used to build the
+/// paths searched.
+pub fn init<'a>(buf: &'a mut [u8], path: &'a mut [u8]) -> Loaded<'a> {
+    let n = load_terminfo(buf, path);
+    let (buf, path): (&'a [u8], &'a [u8]) = (buf, path);
+    let info = match n.and_then(|n| TermInfo::parse(&buf[0..n])) {
+        Some(info) => info,
+        None => return Loaded { info: None, spec: init_builtin(), path: None },
+    };
 
+    let cap = |name| info.string(name).unwrap_or(str0_utf8!(""));
     let mut spec = Spec::empty;
-    for i in 0..TB_KEYS_NUM {
-        spec.keys[i] = NulStr::new_unchecked(tab.get(u16_le(*str.get(ti_keys[i] as usize)?) as usize)?)
+    for i in 0..TB_KEYS_NUM { spec.keys[i] = cap(ti_key_names[i]) }
+    for i in 0..T_FUNCS_NUM { spec.funcs[i] = cap(ti_func_names[i]) }
+    Loaded { info: Some(info), spec: Some(spec), path: Some(unsafe { Nul::new_unchecked(path.as_ptr()) }) }
+}
+
+/// Compiled terminfo entry, parsed in place
+///
+/// Capabilities are looked up by their terminfo names, e.g. `am`, `colors`, `cup`, or the names
+/// of extended capabilities, e.g. `Tc`, `RGB`, `Smulx`; those absent and those cancelled alike
+/// are unset.
+#[derive(Clone, Copy)]
+pub struct TermInfo<'a> {
+    names: &'a [u8],
+    /// Size of a number: 2 bytes, or 4 in the 32-bit format of ncurses 6.1
+    num_size: usize,
+    bools: &'a [u8],
+    nums: &'a [u8],
+    /// Offsets into `table`
+    strs: &'a [u8],
+    table: &'a [u8],
+    ext: Ext<'a>,
+}
+
+/// Extended capabilities, which are named in the entry itself
+#[derive(Debug, Default, Clone, Copy)]
+struct Ext<'a> {
+    bools: &'a [u8],
+    nums: &'a [u8],
+    /// Offsets into `table`
+    strs: &'a [u8],
+    /// Offsets into `table[names_base..]`: of the booleans' names, then the numbers', then the
+    /// strings'
+    names: &'a [u8],
+    table: &'a [u8],
+    names_base: usize,
+    /// Numbers of booleans, numbers, and strings
+    counts: [usize; 3],
+}
+
+impl<'a> fmt::Debug for TermInfo<'a> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("TermInfo").field(&self.names()).finish()
     }
-    for i in 0..T_FUNCS_NUM {
-        spec.funcs[i] = NulStr::new_unchecked(tab.get(u16_le(*str.get(ti_funcs[i] as usize)?) as usize)?)
+}
+
+impl<'a> TermInfo<'a> {
+    /// Parse a compiled terminfo entry, as in a terminfo database, or return `None` if `bs` is
+    /// not one.
+    pub fn parse(bs: &'a [u8]) -> Option<Self> {
+        let (hdr, bs) = bs.try_split_at(TI_HEADER_LENGTH << 1)?;
+        let h = |i: usize| u16_le([hdr[i << 1], hdr[i << 1 | 1]]) as usize;
+        let num_size = match h(0) as u16 { TI_MAGIC => 2, TI_MAGIC_32 => 4, _ => return None };
+        let (names, bs) = bs.try_split_at(h(1))?;
+        let (bools, bs) = bs.try_split_at(h(2))?;
+        // the numbers are aligned to 2 bytes
+        let bs = bs.get(h(1) + h(2) & 1..)?;
+        let (nums, bs) = bs.try_split_at(h(3) * num_size)?;
+        let (strs, bs) = bs.try_split_at(h(4) << 1)?;
+        let (table, bs) = bs.try_split_at(h(5))?;
+        // so is the extended section, if any
+        let ext = bs.get(h(5) & 1..).and_then(|bs| Ext::parse(bs, num_size)).unwrap_or_default();
+        Some(Self { names, num_size, bools, nums, strs, table, ext })
     }
-    Some(spec)
-} }
+
+    /// Return the names of the terminal, separated by `'|'`, the last being its description.
+    #[inline]
+    pub fn names(&self) -> &'a str {
+        let names = self.names.split(|&b| 0 == b).next().unwrap_or(&[]);
+        str::from_utf8(names).unwrap_or("")
+    }
+
+    /// Return whether the boolean capability `name` is set, e.g. `am`, `bce`, `xenl`, `Tc`.
+    pub fn flag(&self, name: &str) -> bool {
+        match index(&bool_names, name) {
+            Some(i) => self.flag_at(i),
+            None => self.ext.index(name, 0).map_or(false, |i| Some(&1) == self.ext.bools.get(i)),
+        }
+    }
+
+    /// Return the numeric capability `name`, e.g. `colors`, `cols`, if it is set.
+    pub fn number(&self, name: &str) -> Option<u32> {
+        match index(&num_names, name) {
+            Some(i) => self.number_at(i),
+            None => number(self.ext.nums, self.num_size, self.ext.index(name, 1)?),
+        }
+    }
+
+    /// Return the string capability `name`, e.g. `cup`, `kf1`, `Smulx`, if it is set.
+    pub fn string(&self, name: &str) -> Option<&'a NulStr> {
+        match index(&str_names, name) {
+            Some(i) => self.string_at(i),
+            None => string(self.ext.strs, self.ext.table, self.ext.index(name, 2)?),
+        }
+    }
+
+    /// Return whether the boolean capability at index `i`, in the standard order, is set.
+    #[inline]
+    pub fn flag_at(&self, i: usize) -> bool { Some(&1) == self.bools.get(i) }
+
+    /// Return the numeric capability at index `i`, in the standard order, if it is set.
+    #[inline]
+    pub fn number_at(&self, i: usize) -> Option<u32> { number(self.nums, self.num_size, i) }
+
+    /// Return the string capability at index `i`, in the standard order, if it is set.
+    #[inline]
+    pub fn string_at(&self, i: usize) -> Option<&'a NulStr> { string(self.strs, self.table, i) }
+}
+
+impl<'a> Ext<'a> {
+    fn parse(bs: &'a [u8], num_size: usize) -> Option<Self> {
+        let (hdr, bs) = bs.try_split_at(TI_EXT_HEADER_LENGTH << 1)?;
+        let h = |i: usize| u16_le([hdr[i << 1], hdr[i << 1 | 1]]) as usize;
+        let (bools, bs) = bs.try_split_at(h(0))?;
+        let bs = bs.get(h(0) & 1..)?;
+        let (nums, bs) = bs.try_split_at(h(1) * num_size)?;
+        let (strs, bs) = bs.try_split_at(h(2) << 1)?;
+        let (names, bs) = bs.try_split_at(h(0) + h(1) + h(2) << 1)?;
+        let table = bs.get(0..h(4))?;
+        // the names follow the last string set
+        let names_base = (0..h(2)).filter_map(|i| string(strs, table, i))
+                                  .map(|s| s[..].as_ptr() as usize - table.as_ptr() as usize + s[..].len() + 1)
+                                  .max().unwrap_or(0);
+        Some(Self { bools, nums, strs, names, table, names_base, counts: [h(0), h(1), h(2)] })
+    }
+
+    /// Return the index of capability `name` of `kind`: 0 for booleans, 1 for numbers, 2 for
+    /// strings.
+    fn index(&self, name: &str, kind: usize) -> Option<usize> {
+        let k = self.counts[0..kind].iter().sum::<usize>();
+        let names = self.table.get(self.names_base..)?;
+        (0..self.counts[kind]).find(|&i| string(self.names, names, k + i).map_or(false, |s| name == &s[..]))
+    }
+}
+
+/// Return the number at index `i` of `nums`, if it is set.
+fn number(nums: &[u8], size: usize, i: usize) -> Option<u32> {
+    let bs = nums.get(i * size..(i + 1) * size)?;
+    let n = bs.iter().rev().fold(0u32, |n, &b| n << 8 | b as u32);
+    // negative if absent or cancelled
+    if 0 == n >> (size << 3) - 1 { Some(n) } else { None }
+}
+
+/// Return the string at index `i` of `strs`, which are offsets into `table`, if it is set.
+fn string<'a>(strs: &[u8], table: &'a [u8], i: usize) -> Option<&'a NulStr> {
+    let o = u16_le([*strs.get(i << 1)?, *strs.get(i << 1 | 1)?]);
+    // negative if absent or cancelled
+    if o >= 0x8000 { return None }
+    let s = table.get(o as usize..)?;
+    str::from_utf8(&s[0..s.iter().position(|&b| 0 == b)?]).ok()?;
+    Some(unsafe { NulStr::new_unchecked(&s[0]) })
+}
+
+#[inline]
+fn index(names: &[&str], name: &str) -> Option<usize> { names.iter().position(|&x| name == x) }
+
+/// Capabilities which the terminal itself tells by XTGETTCAP, merged into a `Spec`
+#[derive(Debug)]
+pub struct Merge {
+    pub spec: Spec<'static>,
+    /// Capabilities of terminfo, from which each probe starts again
+    base: Spec<'static>,
+    pool: &'static mut [u8],
+    used: usize,
+    /// Number of capabilities merged
+    pub n: usize,
+}
+
+impl Merge {
+    #[inline]
+    pub fn new(base: Spec<'static>, pool: &'static mut [u8]) -> Self { Merge { spec: base, base, pool, used: 0, n: 0 } }
+
+    /// Forget what was merged, so the pool can be used again, and return the capabilities of
+    /// terminfo, which must replace any taken from `spec` before, as they are in the pool.
+    #[inline]
+    pub fn reset(&mut self) -> Spec<'static> { self.spec = self.base; self.used = 0; self.n = 0; self.base }
+
+    /// Write a query for each capability which cursebox uses.
+    pub fn write_queries<W: fmt::Write>(mut w: W) -> fmt::Result {
+        for name in ti_key_names.iter().chain(ti_func_names.iter()) {
+            w.write_str("\x1BP+q")?;
+            for b in name.bytes() { write!(w, "{:02X}", b)? }
+            w.write_str("\x1B\\")?;
+        }
+        Ok(())
+    }
+
+    /// Take note of a reply, `1+r name=value` with name and value in hexadecimal.
+    /// Returns whether it was of a capability which cursebox uses.
+    pub fn merge(&mut self, reply: &[u8]) -> bool { (|| {
+        if !reply.starts_with(b"1+r") { return None }
+        let reply = &reply[3..];
+        let k = reply.iter().position(|&b| b'=' == b)?;
+        let mut name = [0; 16];
+        let name = unhex(&reply[..k], &mut name)?;
+        let slot = if let Some(i) = ti_key_names.iter().position(|x| x.as_bytes() == name) {
+            &mut self.spec.keys[i]
+        } else if let Some(i) = ti_func_names.iter().position(|x| x.as_bytes() == name) {
+            &mut self.spec.funcs[i]
+        } else { return None };
+
+        let pool = self.pool.get_mut(self.used..)?;
+        let n = str::from_utf8(unhex(&reply[k+1..], pool)?).ok()?.len();
+        *pool.get_mut(n)? = 0;
+        *slot = unsafe { NulStr::new_unchecked(&*pool.as_ptr()) };
+        self.used += n + 1;
+        self.n += 1;
+        Some(())
+    })().is_some() }
+}
+
+fn unhex<'a>(hex: &[u8], out: &'a mut [u8]) -> Option<&'a [u8]> {
+    fn digit(b: u8) -> Option<u8> { (b as char).to_digit(16).map(|d| d as _) }
+    if 0 != hex.len() & 1 { return None }
+    let out = out.get_mut(0..hex.len() >> 1)?;
+    for (b, xs) in out.iter_mut().zip(hex.chunks(2)) { *b = digit(xs[0])? << 4 | digit(xs[1])? }
+    Some(out)
+}
 
 #[inline(always)]
 fn u16_le(bs: [u8; 2]) -> u16 { bs[0] as u16 | (bs[1] as u16) << 8 }
 
-fn load_terminfo(buf: &mut [u8]) -> Option<usize> {
+/// Read the terminfo entry of `$TERM` into `buf`, searching as ncurses does, and leave in
+/// `path` the path of the file read.
+fn load_terminfo(buf: &mut [u8], path: &mut [u8]) -> Option<usize> {
     let environ = unsafe { unix::env::environ };
     let name = environ.get("TERM".as_bytes())??;
+    // a name with a slash could point at a file elsewhere
+    if name[..].is_empty() || name.iter().any(|&b| b'/' == b) { return None }
 
     if let Some(terminfo) = environ.get("TERMINFO".as_bytes()).and_then(|a|a) {
-        return try_terminfo_path(buf, name, terminfo.iter().cloned())
+        if let Some(n) = try_terminfo_path(buf, path, name, terminfo.iter().cloned()) { return Some(n) }
     }
 
     if let Some(home) = environ.get("HOME".as_bytes()).and_then(|a|a) {
-        if let Some(n) = try_terminfo_path(buf, name, chain![home.iter().cloned(),
-                                                             "/.terminfo".bytes()]) { return Some(n) }
+        if let Some(n) = try_terminfo_path(buf, path, name, chain![home.iter().cloned(),
+                                                                   "/.terminfo".bytes()]) { return Some(n) }
     }
 
     if let Some(dirs) = environ.get("TERMINFO_DIRS".as_bytes()).and_then(|a|a) {
         for dir in dirs[..].split(|&b| b':' == b) {
-            let dir = if 0 == dir.len() { "/usr/share/terminfo".as_bytes() } else { dir };
-            if let Some(n) = try_terminfo_path(buf, name, dir.iter().cloned()) { return Some(n) }
+            // an empty entry stands for the system directories
+            let n = if 0 == dir.len() { try_system_paths(buf, path, name) }
+                    else { try_terminfo_path(buf, path, name, dir.iter().cloned()) };
+            if let Some(n) = n { return Some(n) }
         }
     }
 
-    try_terminfo_path(buf, name, "/usr/share/terminfo".bytes())
+    try_system_paths(buf, path, name)
 }
 
-fn try_terminfo_path<Bs: Iterator<Item = u8>>(buf: &mut [u8], name: &Nul<u8>, bs: Bs) -> Option<usize> {
-    let n = fill_slice(buf, bs)?;
-    try_terminfo_path_helper(buf, name, n)
+static system_dirs: [&str; 3] = ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"];
+
+fn try_system_paths(buf: &mut [u8], path: &mut [u8], name: &Nul<u8>) -> Option<usize> {
+    system_dirs.iter().filter_map(|dir| try_terminfo_path(buf, path, name, dir.bytes())).next()
 }
 
-fn try_terminfo_path_helper(buf: &mut [u8], name: &Nul<u8>, mut k: usize) -> Option<usize> {
-    buf.get_mut(k..k+3)?.copy_from_slice(&[b'/', unsafe { *name.as_ptr() }, b'/']);
-    k += 3;
-    for &b in name.iter() {
-        *buf.get_mut(k)? = b;
-        k += 1;
-    }
-    *buf.get_mut(k)? = 0;
-    let path = unsafe { Nul::new_unchecked(buf.as_ptr()) };
+/// Try the entry `name` in the directory `dir`, under its first letter, e.g. `x/xterm`, or else
+/// the hexadecimal code of that letter, e.g. `78/xterm`, as on macOS.
+fn try_terminfo_path<Bs: Iterator<Item = u8>>(buf: &mut [u8], path: &mut [u8], name: &Nul<u8>,
+                                              dir: Bs) -> Option<usize> {
+    static hex: [u8; 16] = *b"0123456789abcdef";
+    let k = fill_slice(path, dir)?;
+    let c = name[0];
+    try_terminfo_path_helper(buf, path, name, k, &[c])
+        .or_else(|| try_terminfo_path_helper(buf, path, name, k, &[hex[(c >> 4) as usize], hex[(c & 0xF) as usize]]))
+}
+
+fn try_terminfo_path_helper(buf: &mut [u8], path: &mut [u8], name: &Nul<u8>, k: usize, sub: &[u8]) -> Option<usize> {
+    fill_slice(path.get_mut(k..)?, chain!["/".bytes(), sub.iter().cloned(), Some(b'/'), name.iter().cloned(), Some(0)])?;
+    let path = unsafe { Nul::new_unchecked(path.as_ptr()) };
 
     use unix::file::*;
     let mut file = open_at(None, path, OpenMode::RdOnly, None).ok()?;
@@ -162,12 +441,134 @@ fn init_builtin() -> Option<Spec<'static>> {
     None
 }
 
-const TI_MAGIC: u16 = 12;
+const TI_MAGIC: u16 = 0o432;
+const TI_MAGIC_32: u16 = 0o1036;
 const TI_HEADER_LENGTH: usize = 6;
+const TI_EXT_HEADER_LENGTH: usize = 5;
 
-static ti_funcs: [u16; T_FUNCS_NUM] = [28, 40, 16, 13, 5, 39, 36, 27, 26, 34, 89, 88];
-static ti_keys : [u16; TB_KEYS_NUM] = [
-	66, 68 /* apparently not a typo; 67 is F10 for whatever reason */, 69,
-	70, 71, 72, 73, 74, 75, 67, 216, 217, 77, 59, 76, 164, 82, 81, 87, 61,
-	79, 83,
+static ti_func_names: [&str; T_FUNCS_NUM] = [
+    "smcup", "rmcup", "cnorm", "civis", "clear", "sgr0", "smul", "bold", "blink", "rev", "smkx", "rmkx",
+    "cup", "setaf", "setab", "csr",
+];
+static ti_key_names: [&str; TB_KEYS_NUM] = [
+    "kf1", "kf2", "kf3", "kf4", "kf5", "kf6", "kf7", "kf8", "kf9", "kf10", "kf11", "kf12",
+    "kich1", "kdch1", "khome", "kend", "kpp", "knp", "kcuu1", "kcud1", "kcub1", "kcuf1",
+    "kf13", "kf14", "kf15", "kf16", "kf17", "kf18", "kf19", "kf20", "kf21", "kf22", "kf23", "kf24",
+    "kf25", "kf26", "kf27", "kf28", "kf29", "kf30", "kf31", "kf32", "kf33", "kf34", "kf35", "kf36",
+    "kf37", "kf38", "kf39", "kf40", "kf41", "kf42", "kf43", "kf44", "kf45", "kf46", "kf47", "kf48",
+    "kf49", "kf50", "kf51", "kf52", "kf53", "kf54", "kf55", "kf56", "kf57", "kf58", "kf59", "kf60",
+    "kf61", "kf62", "kf63",
+    "kcbt", "ka1", "ka3", "kb2", "kc1", "kc3", "kent", "kbeg", "kHOM", "kEND", "kbs",
+];
+
+pub(crate) static bool_names: [&str; 44] = [
+    "bw", "am", "xsb", "xhp", "xenl", "eo", "gn", "hc", "km", "hs", "in", "da", "db", "mir", "msgr",
+    "os", "eslok", "xt", "hz", "ul", "xon", "nxon", "mc5i", "chts", "nrrmc", "npc", "ndscr", "ccc",
+    "bce", "hls", "xhpa", "crxm", "daisy", "xvpa", "sam", "cpix", "lpix", "OTbs", "OTns", "OTnc",
+    "OTMT", "OTNL", "OTpt", "OTxr",
+];
+pub(crate) static num_names: [&str; 39] = [
+    "cols", "it", "lines", "lm", "xmc", "pb", "vt", "wsl", "nlab", "lh", "lw", "ma", "wnum",
+    "colors", "pairs", "ncv", "bufsz", "spinv", "spinh", "maddr", "mjump", "mcs", "mls", "npins",
+    "orc", "orl", "orhi", "orvi", "cps", "widcs", "btns", "bitwin", "bitype", "OTug", "OTdC",
+    "OTdN", "OTdB", "OTdT", "OTkn",
 ];
+pub(crate) static str_names: [&str; 414] = [
+    "cbt", "bel", "cr", "csr", "tbc", "clear", "el", "ed", "hpa", "cmdch", "cup", "cud1", "home",
+    "civis", "cub1", "mrcup", "cnorm", "cuf1", "ll", "cuu1", "cvvis", "dch1", "dl1", "dsl", "hd",
+    "smacs", "blink", "bold", "smcup", "smdc", "dim", "smir", "invis", "prot", "rev", "smso",
+    "smul", "ech", "rmacs", "sgr0", "rmcup", "rmdc", "rmir", "rmso", "rmul", "flash", "ff", "fsl",
+    "is1", "is2", "is3", "if", "ich1", "il1", "ip", "kbs", "ktbc", "kclr", "kctab", "kdch1", "kdl1",
+    "kcud1", "krmir", "kel", "ked", "kf0", "kf1", "kf10", "kf2", "kf3", "kf4", "kf5", "kf6", "kf7",
+    "kf8", "kf9", "khome", "kich1", "kil1", "kcub1", "kll", "knp", "kpp", "kcuf1", "kind", "kri",
+    "khts", "kcuu1", "rmkx", "smkx", "lf0", "lf1", "lf10", "lf2", "lf3", "lf4", "lf5", "lf6", "lf7",
+    "lf8", "lf9", "rmm", "smm", "nel", "pad", "dch", "dl", "cud", "ich", "indn", "il", "cub", "cuf",
+    "rin", "cuu", "pfkey", "pfloc", "pfx", "mc0", "mc4", "mc5", "rep", "rs1", "rs2", "rs3", "rf",
+    "rc", "vpa", "sc", "ind", "ri", "sgr", "hts", "wind", "ht", "tsl", "uc", "hu", "iprog", "ka1",
+    "ka3", "kb2", "kc1", "kc3", "mc5p", "rmp", "acsc", "pln", "kcbt", "smxon", "rmxon", "smam",
+    "rmam", "xonc", "xoffc", "enacs", "smln", "rmln", "kbeg", "kcan", "kclo", "kcmd", "kcpy",
+    "kcrt", "kend", "kent", "kext", "kfnd", "khlp", "kmrk", "kmsg", "kmov", "knxt", "kopn", "kopt",
+    "kprv", "kprt", "krdo", "kref", "krfr", "krpl", "krst", "kres", "ksav", "kspd", "kund", "kBEG",
+    "kCAN", "kCMD", "kCPY", "kCRT", "kDC", "kDL", "kslt", "kEND", "kEOL", "kEXT", "kFND", "kHLP",
+    "kHOM", "kIC", "kLFT", "kMSG", "kMOV", "kNXT", "kOPT", "kPRV", "kPRT", "kRDO", "kRPL", "kRIT",
+    "kRES", "kSAV", "kSPD", "kUND", "rfi", "kf11", "kf12", "kf13", "kf14", "kf15", "kf16", "kf17",
+    "kf18", "kf19", "kf20", "kf21", "kf22", "kf23", "kf24", "kf25", "kf26", "kf27", "kf28", "kf29",
+    "kf30", "kf31", "kf32", "kf33", "kf34", "kf35", "kf36", "kf37", "kf38", "kf39", "kf40", "kf41",
+    "kf42", "kf43", "kf44", "kf45", "kf46", "kf47", "kf48", "kf49", "kf50", "kf51", "kf52", "kf53",
+    "kf54", "kf55", "kf56", "kf57", "kf58", "kf59", "kf60", "kf61", "kf62", "kf63", "el1", "mgc",
+    "smgl", "smgr", "fln", "sclk", "dclk", "rmclk", "cwin", "wingo", "hup", "dial", "qdial", "tone",
+    "pulse", "hook", "pause", "wait", "u0", "u1", "u2", "u3", "u4", "u5", "u6", "u7", "u8", "u9",
+    "op", "oc", "initc", "initp", "scp", "setf", "setb", "cpi", "lpi", "chr", "cvr", "defc",
+    "swidm", "sdrfq", "sitm", "slm", "smicm", "snlq", "snrmq", "sshm", "ssubm", "ssupm", "sum",
+    "rwidm", "ritm", "rlm", "rmicm", "rshm", "rsubm", "rsupm", "rum", "mhpa", "mcud1", "mcub1",
+    "mcuf1", "mvpa", "mcuu1", "porder", "mcud", "mcub", "mcuf", "mcuu", "scs", "smgb", "smgbp",
+    "smglp", "smgrp", "smgt", "smgtp", "sbim", "scsd", "rbim", "rcsd", "subcs", "supcs", "docr",
+    "zerom", "csnm", "kmous", "minfo", "reqmp", "getm", "setaf", "setab", "pfxl", "devt", "csin",
+    "s0ds", "s1ds", "s2ds", "s3ds", "smglr", "smgtb", "birep", "binel", "bicr", "colornm", "defbi",
+    "endbi", "setcolor", "slines", "dispc", "smpch", "rmpch", "smsc", "rmsc", "pctrm", "scesc",
+    "scesa", "ehhlm", "elhlm", "elohlm", "erhlm", "ethlm", "evhlm", "sgr1", "slength", "OTi2",
+    "OTrs", "OTnl", "OTbc", "OTko", "OTma", "OTG2", "OTG3", "OTG1", "OTG4", "OTGR", "OTGL", "OTGU",
+    "OTGD", "OTGH", "OTGV", "OTGC", "meml", "memu", "box1",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// testdata/cbtest.ti, compiled
+    static cbtest: &[u8] = include_bytes!("../testdata/cbtest");
+    /// testdata/cbtest32.ti, compiled
+    static cbtest32: &[u8] = include_bytes!("../testdata/cbtest32");
+
+    #[test]
+    fn parse() {
+        let ti = TermInfo::parse(cbtest).unwrap();
+        assert_eq!("cbtest|cursebox test", ti.names());
+        assert!(ti.flag("am") && ti.flag("xenl"));
+        assert!(!ti.flag("bce"));
+        assert_eq!((Some(8), Some(80), Some(24)), (ti.number("colors"), ti.number("cols"), ti.number("lines")));
+        assert_eq!(None, ti.number("pairs"));
+        assert_eq!(Some("\x1B[%i%p1%d;%p2%dH"), ti.string("cup").map(|s| &s[..]));
+        assert_eq!(Some("\x7F"), ti.string("kbs").map(|s| &s[..]));
+        assert_eq!(Some("\x1B[m"), ti.string("sgr0").map(|s| &s[..]));
+        assert_eq!(None, ti.string("smso").map(|s| &s[..]));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(TermInfo::parse(&cbtest[0..20]).is_none());
+        // wrong magic
+        assert!(TermInfo::parse(b"\x1A\x02\x15\x00\x05\x00\x0E\x00\x68\x01\x26\x00").is_none());
+    }
+
+    #[test]
+    fn parse_ext() {
+        let ti = TermInfo::parse(cbtest).unwrap();
+        assert_eq!((Some(0), Some(0), Some(0)), (ti.ext.index("Tc", 0), ti.ext.index("U8", 1), ti.ext.index("Smulx", 2)));
+        assert_eq!((None, None), (ti.ext.index("Tc", 2), ti.ext.index("XT", 0)));
+        assert!(ti.flag("Tc"));
+        assert!(!ti.flag("XT"));
+        assert_eq!(Some(1), ti.number("U8"));
+        assert_eq!(Some("\x1B[4:%p1%dm"), ti.string("Smulx").map(|s| &s[..]));
+    }
+
+    #[test]
+    fn parse_no_ext() {
+        // less the extended section
+        let ti = TermInfo::parse(&cbtest[0..cbtest.len() - 45]).unwrap();
+        assert!(ti.flag("am") && !ti.flag("Tc"));
+        assert_eq!(None, ti.string("Smulx").map(|s| &s[..]));
+    }
+
+    #[test]
+    fn parse_32_bit() {
+        let ti = TermInfo::parse(cbtest32).unwrap();
+        assert_eq!(4, ti.num_size);
+        assert_eq!("cbtest32|cursebox test of 32-bit numbers", ti.names());
+        assert!(ti.flag("am") && ti.flag("Tc"));
+        assert_eq!((Some(0x1000000), Some(0x10000), Some(80)), (ti.number("colors"), ti.number("pairs"), ti.number("cols")));
+        assert_eq!(None, ti.number("lines"));
+        assert_eq!((Some(1), Some(70000)), (ti.number("U8"), ti.number("Xw")));
+        assert_eq!(Some("\x7F"), ti.string("kbs").map(|s| &s[..]));
+    }
+}
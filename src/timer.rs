@@ -0,0 +1,116 @@
+use time::{Point, Span};
+
+use TIMERS_MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    id: u64,
+    deadline: Point,
+    period: Option<Span>,
+}
+
+/// Timers which `fetch_event` reports as `Event::Timer`
+#[derive(Debug)]
+pub(crate) struct Timers {
+    slots: [Option<Timer>; TIMERS_MAX],
+}
+
+impl Timers {
+    #[inline]
+    pub(crate) const fn new() -> Self { Self { slots: [None; TIMERS_MAX] } }
+
+    /// Set timer `id` to fire after `delay`, and then every `period` if any, replacing any timer
+    /// of the same `id`. Returns false if too many are set.
+    pub(crate) fn set(&mut self, id: u64, delay: Span, period: Option<Span>) -> bool {
+        let timer = Timer { id, deadline: Point::now() + delay, period };
+        let slot = match self.slots.iter().position(|t| t.map_or(false, |t| id == t.id)) {
+            Some(k) => k,
+            None => match self.slots.iter().position(Option::is_none) { Some(k) => k, None => return false },
+        };
+        self.slots[slot] = Some(timer);
+        true
+    }
+
+    /// Cancel timer `id`. Returns whether it was set.
+    pub(crate) fn cancel(&mut self, id: u64) -> bool {
+        match self.slots.iter_mut().find(|t| t.map_or(false, |t| id == t.id)) {
+            Some(t) => { *t = None; true },
+            None => false,
+        }
+    }
+
+    /// Return when the next timer fires, if any is set.
+    pub(crate) fn next_deadline(&self) -> Option<Point> {
+        self.slots.iter().filter_map(|t| t.map(|t| t.deadline)).min()
+    }
+
+    /// Fire the timer most overdue, if any is due, and return its id.
+    pub(crate) fn fire(&mut self) -> Option<u64> {
+        let now = Point::now();
+        let slot = self.slots.iter_mut().filter(|t| t.map_or(false, |t| t.deadline <= now))
+                       .min_by_key(|t| t.map(|t| t.deadline))?;
+        let timer = slot.take()?;
+        // a repeating timer which fell behind skips the ticks it missed rather than fire in a burst
+        *slot = timer.period.map(|p| {
+            let deadline = timer.deadline + p;
+            Timer { deadline: if deadline > now { deadline } else { now + p }, ..timer }
+        });
+        Some(timer.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const sec: i128 = 1_000_000_000;
+
+    #[test]
+    fn fire() {
+        let mut ts = Timers::new();
+        assert_eq!(None, ts.next_deadline());
+        assert!(ts.set(1, Span::from_ns(-sec), None));
+        assert!(ts.set(2, Span::from_ns(-2*sec), None));
+        assert!(ts.set(3, Span::from_ns(3600*sec), None));
+        assert_eq!(ts.slots[1].map(|t| t.deadline), ts.next_deadline());
+        // the most overdue first
+        assert_eq!(Some(2), ts.fire());
+        assert_eq!(Some(1), ts.fire());
+        assert_eq!(None, ts.fire());
+        assert_eq!(ts.slots[2].map(|t| t.deadline), ts.next_deadline());
+        assert!(ts.cancel(3));
+        assert!(!ts.cancel(3));
+        assert_eq!(None, ts.next_deadline());
+    }
+
+    #[test]
+    fn set() {
+        let mut ts = Timers::new();
+        for id in 0..TIMERS_MAX as u64 { assert!(ts.set(id, Span::from_ns(sec), None)) }
+        assert!(!ts.set(!0, Span::from_ns(sec), None));
+        // the same id is replaced
+        assert!(ts.set(0, Span::from_ns(-sec), None));
+        assert_eq!(Some(0), ts.fire());
+        assert_eq!(None, ts.fire());
+    }
+
+    #[test]
+    fn repeat() {
+        let mut ts = Timers::new();
+        let period = Span::from_ns(sec);
+        // on time, it fires again one period after it was due
+        assert!(ts.set(1, Span::from_ns(-sec/2), Some(period)));
+        let deadline = ts.next_deadline().unwrap();
+        assert_eq!(Some(1), ts.fire());
+        assert_eq!(Some(deadline + period), ts.next_deadline());
+        assert_eq!(None, ts.fire());
+
+        // behind, it skips the ticks missed, and fires once
+        assert!(ts.set(1, Span::from_ns(-10*sec - sec/2), Some(period)));
+        let t = Point::now();
+        assert_eq!(Some(1), ts.fire());
+        let deadline = ts.next_deadline().unwrap();
+        assert!(deadline >= t + period && deadline <= Point::now() + period);
+        assert_eq!(None, ts.fire());
+    }
+}
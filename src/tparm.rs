@@ -0,0 +1,469 @@
+//! Evaluation of parameterized terminfo strings, e.g. `cup` and `setaf`, and of their padding
+
+use core::{fmt::{self, Write}, str};
+
+use terminfo::TermInfo;
+
+const STACK_SIZE: usize = 16;
+
+/// Parameter of a terminfo string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Param<'a> {
+    Int(i32),
+    Str(&'a str),
+}
+
+impl<'a> Param<'a> {
+    #[inline]
+    fn int(self) -> i32 { match self { Param::Int(n) => n, Param::Str(_) => 0 } }
+
+    #[inline]
+    fn str(self) -> &'a str { match self { Param::Int(_) => "", Param::Str(s) => s } }
+}
+
+struct Stack<'a> {
+    xs: [Param<'a>; STACK_SIZE],
+    n: usize,
+}
+
+impl<'a> Stack<'a> {
+    /// Push `x`, or drop it if the stack is full.
+    #[inline]
+    fn push(&mut self, x: Param<'a>) {
+        if let Some(p) = self.xs.get_mut(self.n) { *p = x; self.n += 1 }
+    }
+
+    /// Pop the top, or 0 if the stack is empty, as curses does.
+    #[inline]
+    fn pop(&mut self) -> Param<'a> {
+        if 0 == self.n { return Param::Int(0) }
+        self.n -= 1;
+        self.xs[self.n]
+    }
+
+    #[inline]
+    fn push_bool(&mut self, b: bool) { self.push(Param::Int(b as _)) }
+}
+
+/// Write `cap` with `params` substituted per its `%` codes, as `tparm` of curses does.
+///
+/// Unknown codes are written as they are, less the `%`. The variables, both dynamic (`%Pa`) and
+/// static (`%PA`), last only while this runs.
+pub fn tparm<W: fmt::Write>(mut w: W, cap: &str, params: &[Param]) -> fmt::Result {
+    let mut ps = [Param::Int(0); 9];
+    for (p, &q) in ps.iter_mut().zip(params) { *p = q }
+    let mut stack = Stack { xs: [Param::Int(0); STACK_SIZE], n: 0 };
+    let mut vars = [Param::Int(0); 52];
+    let var = |b: u8| match b {
+        b'a'..=b'z' => Some((b - b'a') as usize),
+        b'A'..=b'Z' => Some((b - b'A') as usize + 26),
+        _ => None,
+    };
+
+    let bs = cap.as_bytes();
+    let mut k = 0;
+    while k < bs.len() {
+        let l = match bs[k..].iter().position(|&b| b'%' == b) {
+            Some(l) => k + l,
+            None => return w.write_str(&cap[k..]),
+        };
+        w.write_str(&cap[k..l])?;
+        let c = match bs.get(l + 1) { Some(&c) => c, None => break };
+        k = l + 2;
+        match c {
+            b'%' => w.write_char('%')?,
+            b'c' => if let Some(c) = ::core::char::from_u32(stack.pop().int() as _) { w.write_char(c)? },
+            b'p' => match bs.get(k) {
+                Some(&d @ b'1'..=b'9') => { stack.push(ps[(d - b'1') as usize]); k += 1 },
+                _ => k = l + 1,
+            },
+            b'P' => match bs.get(k).and_then(|&b| var(b)) {
+                Some(i) => { vars[i] = stack.pop(); k += 1 },
+                None => k = l + 1,
+            },
+            b'g' => match bs.get(k).and_then(|&b| var(b)) {
+                Some(i) => { stack.push(vars[i]); k += 1 },
+                None => k = l + 1,
+            },
+            b'\'' => match cap[k..].chars().next() {
+                Some(c) if Some(&b'\'') == bs.get(k + c.len_utf8()) => {
+                    stack.push(Param::Int(c as _));
+                    k += c.len_utf8() + 1;
+                },
+                _ => k = l + 1,
+            },
+            b'{' => match bs[k..].iter().position(|&b| b'}' == b).and_then(|m| cap[k..k+m].parse().ok().map(|n| (m, n))) {
+                Some((m, n)) => { stack.push(Param::Int(n)); k += m + 1 },
+                None => k = l + 1,
+            },
+            b'l' => { let n = stack.pop().str().chars().count(); stack.push(Param::Int(n as _)) },
+            b'+' | b'-' | b'*' | b'/' | b'm' | b'&' | b'|' | b'^' | b'=' | b'>' | b'<' | b'A' | b'O' => {
+                let (y, x) = (stack.pop().int(), stack.pop().int());
+                match c {
+                    b'+' => stack.push(Param::Int(x.wrapping_add(y))),
+                    b'-' => stack.push(Param::Int(x.wrapping_sub(y))),
+                    b'*' => stack.push(Param::Int(x.wrapping_mul(y))),
+                    b'/' => stack.push(Param::Int(x.checked_div(y).unwrap_or(0))),
+                    b'm' => stack.push(Param::Int(x.checked_rem(y).unwrap_or(0))),
+                    b'&' => stack.push(Param::Int(x & y)),
+                    b'|' => stack.push(Param::Int(x | y)),
+                    b'^' => stack.push(Param::Int(x ^ y)),
+                    b'=' => stack.push_bool(x == y),
+                    b'>' => stack.push_bool(x > y),
+                    b'<' => stack.push_bool(x < y),
+                    b'A' => stack.push_bool(0 != x && 0 != y),
+                    _    => stack.push_bool(0 != x || 0 != y),
+                }
+            },
+            b'!' => { let x = stack.pop().int(); stack.push_bool(0 == x) },
+            b'~' => { let x = stack.pop().int(); stack.push(Param::Int(!x)) },
+            b'i' => for p in &mut ps[0..2] { if let Param::Int(ref mut n) = *p { *n += 1 } },
+            b'?' | b';' => (),
+            b't' => if 0 == stack.pop().int() { k = skip(bs, k, true) },
+            // the condition held, so the rest is skipped
+            b'e' => k = skip(bs, k, false),
+            _ => match parse_format(bs, l + 1) {
+                Some((f, m)) => { printf(&mut w, &f, stack.pop())?; k = m },
+                None => k = l + 1,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Return where the code after `%t` or `%e` at `k` ends, i.e. past its matching `%e`, if
+/// `to_else`, or `%;`, skipping nested conditionals.
+fn skip(bs: &[u8], mut k: usize, to_else: bool) -> usize {
+    let mut level = 0;
+    while k + 1 < bs.len() {
+        if b'%' != bs[k] { k += 1; continue }
+        k += 2;
+        match bs[k - 1] {
+            b'?' => level += 1,
+            b';' if 0 == level => return k,
+            b';' => level -= 1,
+            b'e' if 0 == level && to_else => return k,
+            _ => (),
+        }
+    }
+    bs.len()
+}
+
+/// `%[[:]flags][width[.precision]][doxXs]`
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    prec: Option<usize>,
+    conv: u8,
+}
+
+/// Parse the format at `j`, past the `%`, and return it and where it ends.
+fn parse_format(bs: &[u8], mut j: usize) -> Option<(Format, usize)> {
+    let mut f = Format { left: false, plus: false, space: false, alt: false, zero: false,
+                         width: 0, prec: None, conv: 0 };
+    // `-` and `+` are flags only after `:`, else they are arithmetic.
+    let colon = Some(&b':') == bs.get(j);
+    if colon { j += 1 }
+    loop {
+        match *bs.get(j)? {
+            b'-' if colon => f.left = true,
+            b'+' if colon => f.plus = true,
+            b'#' => f.alt = true,
+            b' ' => f.space = true,
+            _ => break,
+        }
+        j += 1;
+    }
+    f.zero = Some(&b'0') == bs.get(j);
+    let (width, m) = parse_decimal(bs, j);
+    f.width = width;
+    j = m;
+    if Some(&b'.') == bs.get(j) {
+        let (prec, m) = parse_decimal(bs, j + 1);
+        f.prec = Some(prec);
+        j = m;
+    }
+    match *bs.get(j)? {
+        c @ b'd' | c @ b'o' | c @ b'x' | c @ b'X' | c @ b's' => f.conv = c,
+        _ => return None,
+    }
+    Some((f, j + 1))
+}
+
+fn parse_decimal(bs: &[u8], mut j: usize) -> (usize, usize) {
+    let mut n = 0usize;
+    while let Some(&d @ b'0'..=b'9') = bs.get(j) {
+        n = n.saturating_mul(10).saturating_add((d - b'0') as usize);
+        j += 1;
+    }
+    (n, j)
+}
+
+fn printf<W: fmt::Write>(w: W, f: &Format, p: Param) -> fmt::Result {
+    let mut bs = [0; 24];
+    let mut buf = Buf::new(&mut bs);
+    let (prefix, body) = match f.conv {
+        b's' => {
+            let s = p.str();
+            let s = match f.prec {
+                Some(m) => &s[0..s.char_indices().nth(m).map_or(s.len(), |(k, _)| k)],
+                None => s,
+            };
+            return pad(w, f, "", s)
+        },
+        b'd' => {
+            let n = p.int();
+            write!(buf, "{}", (n as i64).abs())?;
+            (if n < 0 { "-" } else if f.plus { "+" } else if f.space { " " } else { "" }, buf.as_str())
+        },
+        b'o' => {
+            write!(buf, "{:o}", p.int() as u32)?;
+            (if f.alt && 0 != p.int() { "0" } else { "" }, buf.as_str())
+        },
+        b'x' => {
+            write!(buf, "{:x}", p.int() as u32)?;
+            (if f.alt && 0 != p.int() { "0x" } else { "" }, buf.as_str())
+        },
+        _ => {
+            write!(buf, "{:X}", p.int() as u32)?;
+            (if f.alt && 0 != p.int() { "0X" } else { "" }, buf.as_str())
+        },
+    };
+    pad(w, f, prefix, body)
+}
+
+/// Write `prefix` and `body`, padded per `f`; `body` is a number unless `f.conv` is `s`.
+fn pad<W: fmt::Write>(mut w: W, f: &Format, prefix: &str, body: &str) -> fmt::Result {
+    let len = body.chars().count();
+    let mut zeros = match (f.conv, f.prec) {
+        (b's', _) => 0,
+        (_, Some(m)) => m.saturating_sub(len),
+        (_, None) if f.zero && !f.left => f.width.saturating_sub(prefix.len() + len),
+        (_, None) => 0,
+    };
+    let spaces = f.width.saturating_sub(prefix.len() + zeros + len);
+    if !f.left { for _ in 0..spaces { w.write_char(' ')? } }
+    w.write_str(prefix)?;
+    while zeros > 0 { w.write_char('0')?; zeros -= 1 }
+    w.write_str(body)?;
+    if f.left { for _ in 0..spaces { w.write_char(' ')? } }
+    Ok(())
+}
+
+/// How to pad, per terminfo and the line speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Padding {
+    /// Pad character, or `None` if the terminal has none (`npc`), in which case padding is stripped
+    pub ch: Option<char>,
+    /// Line speed, in bits per second
+    pub baud: u32,
+    /// Whether to pad where not mandatory, i.e. unless the terminal has flow control (`xon`) or
+    /// the line is slower than `pb`
+    pub normal: bool,
+}
+
+impl Padding {
+    /// Strip all padding.
+    pub const none: Self = Padding { ch: None, baud: 0, normal: false };
+
+    /// Pad per `info` at `baud` bits per second.
+    pub fn new(info: &TermInfo, baud: u32) -> Self {
+        let ch = if info.flag("npc") { None }
+                 else { Some(info.string("pad").and_then(|s| s[..].chars().next()).unwrap_or('\0')) };
+        let normal = !info.flag("xon") && info.number("pb").map_or(true, |pb| baud >= pb);
+        Padding { ch, baud, normal }
+    }
+
+    /// Write the pad characters to delay `tenths` of a millisecond.
+    fn write<W: fmt::Write>(&self, mut w: W, tenths: u32) -> fmt::Result {
+        let ch = match self.ch { Some(ch) => ch, None => return Ok(()) };
+        // 9 bits a character, as curses reckons it
+        for _ in 0..tenths as u64 * self.baud as u64 / 90000 { w.write_char(ch)? }
+        Ok(())
+    }
+}
+
+/// Write `cap` with its padding turned into pad characters per `pad`, as `tputs` of curses does.
+///
+/// Padding is `$<delay>`, where `delay` is in milliseconds, with a tenth at most, and may be
+/// followed by `*`, meaning it is per line affected, here taken to be 1, and `/`, meaning it
+/// is mandatory.
+pub fn tputs<W: fmt::Write>(mut w: W, cap: &str, pad: &Padding) -> fmt::Result {
+    let mut k = 0;
+    while let Some(l) = cap[k..].find("$<").map(|l| k + l) {
+        w.write_str(&cap[k..l])?;
+        match parse_delay(cap.as_bytes(), l + 2) {
+            Some((tenths, mandatory, m)) => {
+                if mandatory || pad.normal { pad.write(&mut w, tenths)? }
+                k = m;
+            },
+            None => { w.write_str("$<")?; k = l + 2 },
+        }
+    }
+    w.write_str(&cap[k..])
+}
+
+/// Parse the delay at `j`, past the `$<`, and return it in tenths of a millisecond, whether it
+/// is mandatory, and where it ends.
+fn parse_delay(bs: &[u8], j: usize) -> Option<(u32, bool, usize)> {
+    let (ms, mut j) = parse_decimal(bs, j);
+    let mut tenths = ms.saturating_mul(10);
+    if Some(&b'.') == bs.get(j) {
+        if let Some(&d @ b'0'..=b'9') = bs.get(j + 1) { tenths = tenths.saturating_add((d - b'0') as _) }
+        // finer than a tenth is ignored.
+        j = parse_decimal(bs, j + 1).1;
+    }
+    let mut mandatory = false;
+    loop {
+        match *bs.get(j)? {
+            b'*' => (),
+            b'/' => mandatory = true,
+            b'>' => break,
+            _ => return None,
+        }
+        j += 1;
+    }
+    Some((::core::cmp::min(tenths, !0u32 as usize) as _, mandatory, j + 1))
+}
+
+/// Buffer to format into
+pub(crate) struct Buf<'a> {
+    bs: &'a mut [u8],
+    n: usize,
+}
+
+impl<'a> Buf<'a> {
+    #[inline]
+    pub(crate) fn new(bs: &'a mut [u8]) -> Self { Buf { bs, n: 0 } }
+
+    #[inline]
+    pub(crate) fn as_bytes(&self) -> &[u8] { &self.bs[0..self.n] }
+
+    #[inline]
+    pub(crate) fn as_str(&self) -> &str { str::from_utf8(self.as_bytes()).unwrap_or("") }
+}
+
+impl<'a> fmt::Write for Buf<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.bs.get_mut(self.n..self.n + s.len()).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.n += s.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::string::String;
+    use super::*;
+
+    fn eval(cap: &str, params: &[Param]) -> String {
+        let mut s = String::new();
+        tparm(&mut s, cap, params).unwrap();
+        s
+    }
+
+    fn ints(ns: &[i32]) -> [Param<'static>; 9] {
+        let mut ps = [Param::Int(0); 9];
+        for (p, &n) in ps.iter_mut().zip(ns) { *p = Param::Int(n) }
+        ps
+    }
+
+    #[test]
+    fn cup() {
+        // vt100 and xterm alike
+        assert_eq!("\x1B[5;10H", eval("\x1B[%i%p1%d;%p2%dH", &ints(&[4, 9])));
+        assert_eq!("\x1B[1;1H", eval("\x1B[%i%p1%d;%p2%dH", &[]));
+    }
+
+    #[test]
+    fn setaf() {
+        // xterm-256color
+        let setaf = "\x1B[%?%p1%{8}%<%t3%p1%d%e%p1%{16}%<%t9%p1%{8}%-%d%e38;5;%p1%d%;m";
+        assert_eq!("\x1B[31m", eval(setaf, &ints(&[1])));
+        assert_eq!("\x1B[91m", eval(setaf, &ints(&[9])));
+        assert_eq!("\x1B[38;5;200m", eval(setaf, &ints(&[200])));
+    }
+
+    #[test]
+    fn sgr() {
+        // xterm
+        let sgr = "%?%p9%t\x1B(0%e\x1B(B%;\x1B[0%?%p6%t;1%;%?%p5%t;2%;%?%p2%t;4%;%?%p1%p3%|%t;7%;%?%p4%t;5%;%?%p7%t;8%;m";
+        assert_eq!("\x1B(B\x1B[0m", eval(sgr, &ints(&[])));
+        assert_eq!("\x1B(B\x1B[0;1m", eval(sgr, &ints(&[0, 0, 0, 0, 0, 1])));
+        assert_eq!("\x1B(0\x1B[0;4;7m", eval(sgr, &ints(&[1, 1, 0, 0, 0, 0, 0, 0, 1])));
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let cap = "%?%p1%t%?%p2%tA%eB%;%eC%;.";
+        assert_eq!("A.", eval(cap, &ints(&[1, 1])));
+        assert_eq!("B.", eval(cap, &ints(&[1, 0])));
+        assert_eq!("C.", eval(cap, &ints(&[0, 1])));
+    }
+
+    #[test]
+    fn increment() {
+        // only the first 2 parameters
+        assert_eq!("1 2 2", eval("%i%p1%d %p2%d %p3%d", &ints(&[0, 1, 2])));
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("42   |", eval("%p1%:-5d|", &ints(&[42])));
+        assert_eq!("   42|", eval("%p1%5d|", &ints(&[42])));
+        assert_eq!("-0007", eval("%p1%05d", &ints(&[-7])));
+        assert_eq!("+7", eval("%p1%:+d", &ints(&[7])));
+        assert_eq!("0x1f 1F", eval("%p1%#x %p1%X", &ints(&[31])));
+        assert_eq!("ab", eval("%p1%.2s", &[Param::Str("abc")]));
+        // without `:`, `-` is subtraction
+        assert_eq!("3", eval("%p1%p2%-%d", &ints(&[5, 2])));
+        assert_eq!("%A", eval("%%%'A'%c", &[]));
+    }
+
+    #[test]
+    fn parse_formats() {
+        let (f, j) = parse_format(b"%:-5d", 1).unwrap();
+        assert!(f.left && !f.zero);
+        assert_eq!((5, None, b'd', 5), (f.width, f.prec, f.conv, j));
+        let (f, j) = parse_format(b"%03.2x;", 1).unwrap();
+        assert!(f.zero && !f.left);
+        assert_eq!((3, Some(2), b'x', 6), (f.width, f.prec, f.conv, j));
+        assert!(parse_format(b"%-5d", 1).is_none());
+        assert!(parse_format(b"%5", 1).is_none());
+    }
+
+    #[test]
+    fn skips() {
+        let bs = b"A%?x%ey%;B%eC%;D";
+        assert_eq!(12, skip(bs, 0, true));
+        assert_eq!(15, skip(bs, 0, false));
+        assert_eq!(15, skip(bs, 12, true));
+        // no `%;` to end this one
+        let bs = b"A%?B%;";
+        assert_eq!(bs.len(), skip(bs, 0, false));
+    }
+
+    #[test]
+    fn padding() {
+        let mut s = String::new();
+        let pad = Padding { ch: Some('\0'), baud: 9600, normal: true };
+        tputs(&mut s, "\x1B[H$<5*/>", &pad).unwrap();
+        assert_eq!("\x1B[H\0\0\0\0\0", s);
+
+        let pad = Padding { normal: false, ..pad };
+        s.clear();
+        tputs(&mut s, "a$<5>b$<1.5/>c", &pad).unwrap();
+        assert_eq!("ab\0c", s);
+
+        s.clear();
+        tputs(&mut s, "\x1B[H$<5*/>$<x", &Padding::none).unwrap();
+        assert_eq!("\x1B[H$<x", s);
+    }
+}
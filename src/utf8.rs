@@ -1,40 +1,80 @@
 use core::num::NonZeroUsize;
 
-static utf8_length: [L; 256] = [
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,L1,
-  L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,L2,
-  L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L3,L4,L4,L4,L4,L4,L4,L4,L4,L5,L5,L5,L5,L6,L6,L1,L1,
-];
-
-static utf8_mask: [u8; 7] = [0, 0x7F, 0x1F, 0x0F, 0x07, 0x03, 0x01];
-
-#[derive(Clone, Copy)]
-#[repr(u8)]
-enum L {
-    L1 = 1,
-    L2 = 2,
-    L3 = 3,
-    L4 = 4,
-    L5 = 5,
-    L6 = 6,
+/// What `decode` found at the beginning of its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    Char(char, NonZeroUsize),
+    /// The input may be the beginning of a character, so more is needed.
+    Partial,
+    /// The input begins with so many bytes of no character: the longest prefix of a
+    /// well-formed sequence, or else 1 byte, as Unicode counts them for replacement.
+    Invalid(NonZeroUsize),
 }
-use self::L::*;
-
-pub fn decode(bs: &[u8]) -> Option<(u32, NonZeroUsize)> {
-    let bs_l = bs.len();
-    let (&b0, bs) = bs.split_first()?;
-    let l = utf8_length[b0 as usize] as usize;
-    if l > bs_l { return None }
-    let l = NonZeroUsize::new(l)?;
-    let mut x = (b0 & utf8_mask[l.get()]) as u32;
-    for b in bs[0..l.get()].iter().cloned() {
-        x <<= 6;
-        x |= b as u32 & 0x3F;
+
+/// Decode the first character of `bs`, rejecting overlong forms, surrogates, and code points
+/// beyond U+10FFFF.
+pub fn decode(bs: &[u8]) -> Option<Decoded> {
+    let (&b0, _) = bs.split_first()?;
+    let (l, lo, hi) = match b0 {
+        0x00..=0x7F => return Some(Decoded::Char(b0 as char, one)),
+        0xC2..=0xDF => (2, 0x80, 0xBF),
+        0xE0        => (3, 0xA0, 0xBF),
+        0xED        => (3, 0x80, 0x9F),
+        0xE1..=0xEF => (3, 0x80, 0xBF),
+        0xF0        => (4, 0x90, 0xBF),
+        0xF4        => (4, 0x80, 0x8F),
+        0xF1..=0xF3 => (4, 0x80, 0xBF),
+        _ => return Some(Decoded::Invalid(one)),
+    };
+    let mut x = (b0 & (0x7F >> l)) as u32;
+    for k in 1..l {
+        let b = match bs.get(k) { Some(&b) => b, None => return Some(Decoded::Partial) };
+        let (lo, hi) = if 1 == k { (lo, hi) } else { (0x80, 0xBF) };
+        if b < lo || b > hi { return Some(Decoded::Invalid(NonZeroUsize::new(k)?)) }
+        x = x << 6 | (b & 0x3F) as u32;
+    }
+    Some(Decoded::Char(::core::char::from_u32(x)?, NonZeroUsize::new(l)?))
+}
+
+const one: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(1) };
+
+#[cfg(test)]
+mod tests {
+    use core::num::NonZeroUsize;
+    use super::*;
+
+    fn n(n: usize) -> NonZeroUsize { NonZeroUsize::new(n).unwrap() }
+
+    #[test]
+    fn chars() {
+        assert_eq!(None, decode(b""));
+        assert_eq!(Some(Decoded::Char('a', n(1))), decode(b"ab"));
+        assert_eq!(Some(Decoded::Char('é', n(2))), decode("é".as_bytes()));
+        assert_eq!(Some(Decoded::Char('€', n(3))), decode("€".as_bytes()));
+        assert_eq!(Some(Decoded::Char('\u{1F600}', n(4))), decode(b"\xF0\x9F\x98\x80"));
+        assert_eq!(Some(Decoded::Char('\u{10FFFF}', n(4))), decode(b"\xF4\x8F\xBF\xBF"));
+    }
+
+    #[test]
+    fn partial() {
+        assert_eq!(Some(Decoded::Partial), decode(b"\xE2"));
+        assert_eq!(Some(Decoded::Partial), decode(b"\xE2\x82"));
+        assert_eq!(Some(Decoded::Partial), decode(b"\xF0\x9F\x98"));
+    }
+
+    #[test]
+    fn invalid() {
+        // overlong
+        assert_eq!(Some(Decoded::Invalid(n(1))), decode(b"\xC0\x80"));
+        assert_eq!(Some(Decoded::Invalid(n(1))), decode(b"\xE0\x80\x80"));
+        // surrogate
+        assert_eq!(Some(Decoded::Invalid(n(1))), decode(b"\xED\xA0\x80"));
+        // beyond U+10FFFF
+        assert_eq!(Some(Decoded::Invalid(n(1))), decode(b"\xF4\x90\x80\x80"));
+        // stray continuation byte
+        assert_eq!(Some(Decoded::Invalid(n(1))), decode(b"\x80"));
+        // truncated
+        assert_eq!(Some(Decoded::Invalid(n(2))), decode(b"\xE2\x82A"));
+        assert_eq!(Some(Decoded::Invalid(n(3))), decode(b"\xF0\x9F\x98A"));
     }
-    Some((x, l))
 }